@@ -1,3 +1,4 @@
+use output;
 use output::Output;
 use output::OutputLock;
 use output::file::File as InnerFile;
@@ -10,35 +11,64 @@ use std::mem;
 use std::collections::HashMap;
 use crossbeam;
 use std::sync::mpsc::{Sender, channel, Receiver};
-use std::sync::Mutex;
+use std::sync::{Mutex, Condvar};
 use std::io;
 use log::{LogLevel, LogLevelFilter, LogLocation, SetLoggerError, LogMetadata, LogRecord};
 use std::thread;
 use crossbeam::sync::MsQueue;
+use redact::Redactor;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 struct EventRouter {
     formatter: Arc<Formatter>,
     output: Vec<Arc<Output>>,
+    redactor: Option<Arc<Redactor>>,
 }
 
 pub struct EventRouterBuilder {
     formatter: Arc<Formatter>,
     output: Vec<Arc<Output>>,
+    redactor: Option<Arc<Redactor>>,
 }
 
 
 impl EventRouter {
     #[inline]
-    fn gateway(&self, queue: &Arc<MsQueue<OutputEntry>>, record: Arc<LogEntry>) {
+    fn gateway(&self, queue: &Arc<MsQueue<OutputEntry>>, progress: &Progress, record: Arc<LogEntry>) {
+        let record = self.redacted(record);
         for output in &self.output {
             let entry = OutputEntry {
                 formatter: self.formatter.clone(),
                 output: output.clone(),
                 entry: record.clone(),
             };
+            progress.enqueued.fetch_add(1, Ordering::SeqCst);
             queue.push(entry);
         }
     }
+
+    //在调用线程上直接写出, 每个输出都当作一批结束
+    fn deliver(&self, record: Arc<LogEntry>) {
+        let record = self.redacted(record);
+        let mut buf = String::with_capacity(256);
+        for output in &self.output {
+            buf.clear();
+            let _ = self.formatter.format(output.has_color(), output.sanitize(), &record, &mut buf);
+            buf.push('\n');
+            output::report(&**output, output.push_record(&record, buf.as_str()));
+            output::report(&**output, output.batch_end());
+        }
+    }
+
+    //写出之前脱敏, 不影响其它路由拿到的记录
+    #[inline]
+    fn redacted(&self, record: Arc<LogEntry>) -> Arc<LogEntry> {
+        match self.redactor.as_ref().and_then(|r| r.redact(record.args())) {
+            Some(msg) => Arc::new(record.with_message(msg)),
+            None => record,
+        }
+    }
 }
 
 impl EventRouterBuilder
@@ -49,9 +79,15 @@ impl EventRouterBuilder
         EventRouterBuilder {
             formatter: formater.clone(),
             output: vec!(),
+            redactor: None,
         }
     }
 
+    pub fn redact(&mut self, redactor: Arc<Redactor>) -> &mut Self {
+        self.redactor = Some(redactor);
+        self
+    }
+
     pub fn add<T: Output>(&mut self, output: Arc<T>) -> &mut Self {
         self.output.push(output);
         self
@@ -61,6 +97,7 @@ impl EventRouterBuilder
         EventRouter {
             formatter: self.formatter.clone(),
             output: self.output.clone(),
+            redactor: self.redactor.clone(),
         }
     }
 }
@@ -98,46 +135,145 @@ struct OutputEntry {
     formatter: Arc<Formatter>,
 }
 
+//一批最多的记录数和最长的时间(毫秒)
+const BATCH_RECORDS: usize = 1024;
+const BATCH_MILLIS: u64 = 100;
+
+//入队和写完的记录数, 队列是先进先出的, 写完的数量追上某一时刻的入队数量时, 那之前入队的记录都已经写完
+struct Progress {
+    enqueued: AtomicUsize,
+    written: Mutex<usize>,
+    done: Condvar,
+}
+
 pub struct FileChannel {
     queue: Arc<MsQueue<OutputEntry>>,
     router: HashMap<Option<i32>, Vec<EventRouter>>,
+    progress: Arc<Progress>,
 }
 
 impl FileChannel {
     pub fn new(builder: &mut EventRouterFilterBuilder) -> FileChannel {
         let queue = Arc::new(MsQueue::new());
+        let progress = Arc::new(Progress {
+            enqueued: AtomicUsize::new(0),
+            written: Mutex::new(0),
+            done: Condvar::new(),
+        });
 
         unsafe {
             let q = queue.clone();
-            crossbeam::spawn_unsafe(move || FileChannel::work(q));
+            let p = progress.clone();
+            crossbeam::spawn_unsafe(move || FileChannel::work(q, p));
         }
 
         FileChannel {
             queue: queue.clone(),
-            router: mem::replace(builder.build(), HashMap::new())
+            router: mem::replace(builder.build(), HashMap::new()),
+            progress: progress,
         }
     }
-    fn work(rx: Arc<MsQueue<OutputEntry>>) {
+    fn work(rx: Arc<MsQueue<OutputEntry>>, progress: Arc<Progress>) {
+        //每个worker复用同一个buffer,避免每条记录重新分配
+        let mut buf = String::with_capacity(256);
+        //这一批写过的输出, 记录数和开始的时间
+        let mut batch: Vec<Arc<Output>> = Vec::new();
+        let mut batch_records = 0;
+        let mut batch_started = Instant::now();
         loop {
-            let entry = rx.clone().pop();
-            let data = entry.formatter.parse(entry.output.has_color(), &(entry.entry));
-            entry.output.push(data.as_str());
-            drop(entry)
+            let entry = rx.pop();
+            buf.clear();
+            let _ = entry.formatter.format(entry.output.has_color(), entry.output.sanitize(), &(entry.entry), &mut buf);
+            //换行也放进buf, 输出用一次write写出整条记录
+            buf.push('\n');
+            output::report(&*entry.output, entry.output.push_record(&(entry.entry), buf.as_str()));
+            if !batch.iter().any(|output| Arc::ptr_eq(output, &entry.output)) {
+                batch.push(entry.output.clone());
+            }
+            drop(entry);
+            if batch_records == 0 {
+                batch_started = Instant::now();
+            }
+            batch_records += 1;
+
+            //队列空了说明一批写完; 一直有记录进来时按记录数和时间分批, 不会一直不落盘
+            if rx.is_empty() || batch_records >= BATCH_RECORDS || batch_started.elapsed() >= Duration::from_millis(BATCH_MILLIS) {
+                for output in batch.drain(..) {
+                    output::report(&*output, output.batch_end());
+                }
+                batch_records = 0;
+            }
+            *progress.written.lock().unwrap() += 1;
+            progress.done.notify_all();
         }
     }
 }
 
 impl Channeled for FileChannel {
     fn send(&self, data: Arc<LogEntry>) {
-        let level = &Some(data.level() as i32);
-        self.router.get(level).or_else(|| {
-            self.router.get(&None)
-        }).map(|routers| {
+        route(&self.router, &data).map(|routers| {
+            for r in routers {
+                r.gateway(&self.queue, &self.progress, data.clone());
+            }
+        });
+    }
+
+    fn flush(&self) {
+        //只等进入flush时已经入队的记录, 其它线程继续写日志也不会一直等下去
+        let target = self.progress.enqueued.load(Ordering::SeqCst);
+        let mut written = self.progress.written.lock().unwrap();
+        while *written < target {
+            written = self.progress.done.wait(written).unwrap();
+        }
+        drop(written);
+        flush_outputs(&self.router);
+    }
+}
+
+//不经过队列, 在调用线程上写出, send返回时记录已经按输出的Durability落盘
+//用于audit等必须确认写入后才能继续的target
+pub struct SyncChannel {
+    router: HashMap<Option<i32>, Vec<EventRouter>>,
+}
+
+impl SyncChannel {
+    pub fn new(builder: &mut EventRouterFilterBuilder) -> SyncChannel {
+        SyncChannel {
+            router: mem::replace(builder.build(), HashMap::new()),
+        }
+    }
+}
+
+impl Channeled for SyncChannel {
+    fn send(&self, data: Arc<LogEntry>) {
+        route(&self.router, &data).map(|routers| {
             for r in routers {
-                r.gateway(&self.queue, data.clone());
+                r.deliver(data.clone());
             }
         });
     }
+
+    fn flush(&self) {
+        flush_outputs(&self.router);
+    }
+}
+
+#[inline]
+fn route<'a>(router: &'a HashMap<Option<i32>, Vec<EventRouter>>, data: &LogEntry) -> Option<&'a Vec<EventRouter>> {
+    let level = &Some(data.level() as i32);
+    router.get(level).or_else(|| {
+        router.get(&None)
+    })
+}
+
+fn flush_outputs(router: &HashMap<Option<i32>, Vec<EventRouter>>) {
+    for routers in router.values() {
+        for r in routers {
+            for output in &r.output {
+                output::report(&**output, output.flush());
+            }
+        }
+    }
 }
 
 #[test]
@@ -166,3 +302,31 @@ fn test_file_channel() {
     //    thread::sleep_ms(3000000);
 }
 
+
+#[test]
+fn sync_channel_writes_before_returning() {
+    use std::env;
+    use std::fs;
+    use std::process;
+    use output::file::Durability;
+    use redact::RedactorBuilder;
+    use format;
+
+    let dir = env::temp_dir().join(format!("intro-sync-channel-{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let path = dir.join("audit.log");
+
+    let file = InnerFile::new(&path).unwrap().with_durability(Durability::Record);
+    let formatter = Arc::new(StringFormatter::new("%{level} %{message}"));
+    let mut router = EventRouterBuilder::new(formatter);
+    router.add(Arc::new(OutputLock::new(file, false)))
+        .redact(Arc::new(RedactorBuilder::new().secrets().build()));
+    let mut filter = EventRouterFilterBuilder::new();
+    filter.default(&mut router);
+    let channel = SyncChannel::new(&mut filter);
+
+    channel.send(Arc::new(format::test_entry(LogLevel::Info, "audit", "single_channel.rs", 1, "login token=abc")));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "INFO login token=[REDACTED]\n");
+
+    let _ = fs::remove_dir_all(&dir);
+}
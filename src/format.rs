@@ -1,19 +1,396 @@
 use regex::Regex;
 use std::str;
+use std::fmt;
+use std::fmt::Write;
+use std::error::Error;
 use std::string::String;
 use super::Formatter;
 use super::Parted;
 use super::LogEntry;
+use log::LogLevel;
+use std::collections::HashMap;
+use std::sync::Arc;
 use chrono::prelude::*;
-use chrono::{NaiveDateTime, TimeZone, NaiveDate, Local};
+use chrono::{NaiveDateTime, NaiveDate};
+use chrono::format::{Item, Fixed};
 use time;
-use time::{Timespec, Tm};
+use time::{Timespec, Tm, Duration};
 use level_color;
+use level_color::{Palette, Style};
+use sanitize::{Sanitizer, SanitizeMode};
+
+const PLACEHOLDERS: &'static [&'static str] = &["datetime", "line", "level", "file", "modulePath", "message",
+    "thread", "threadId", "target", "pid", "hostname", "appName", "seq", "elapsed", "delta", "uptime"];
+
+//`%{name:...}...%{/name}`形式的区段
+const SECTIONS: &'static [&'static str] = &["if", "color"];
+
+const LOG_LEVELS: [LogLevel; 5] = [LogLevel::Error, LogLevel::Warn, LogLevel::Info, LogLevel::Debug, LogLevel::Trace];
+
+const RFC2822_ITEMS: &'static [Item<'static>] = &[Item::Fixed(Fixed::RFC2822)];
+const RFC3339_ITEMS: &'static [Item<'static>] = &[Item::Fixed(Fixed::RFC3339)];
 
 
 pub struct Part {
     name: String,
     layout: Option<String>,
+    modifier: Option<Modifier>,
+    offset: usize,
+    datetime: Option<DateTimeLayout>,
+    condition: Option<Condition>,
+    children: Vec<Part>,
+    custom: Option<Arc<Renderer>>,
+    style: Option<PartStyle>,
+}
+
+//`%{color:level}`按调色板取级别对应的样式, 其它为固定样式如`%{color:bold red}`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PartStyle {
+    Level,
+    Fixed(Style),
+}
+
+impl PartStyle {
+    #[inline]
+    fn resolve(&self, palette: &Palette, level: LogLevel) -> Style {
+        match *self {
+            PartStyle::Level => palette.style(level),
+            PartStyle::Fixed(style) => style,
+        }
+    }
+}
+
+//渲染时向下传递的状态, style为外层区段的样式, 内层区段结束后需要恢复
+//header为当前顶层part之前的parts, 供Multiline::Header使用
+#[derive(Copy, Clone)]
+struct Context<'a> {
+    color: bool,
+    palette: &'a Palette,
+    style: Style,
+    multiline: Option<&'a Multiline>,
+    header: &'a [Part],
+    sanitize: SanitizeMode,
+}
+
+//是否转义message, target, thread和自定义占位符中的控制字符及ANSI序列
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Sanitize {
+    //按输出端的设置(Output::sanitize)
+    Auto,
+    //相当于SanitizeMode::Full
+    Always,
+    Never,
+}
+
+//消息中包含换行时的处理方式
+#[derive(Clone, Debug, PartialEq)]
+pub enum Multiline {
+    //原样输出
+    Raw,
+    //写成`\n`和`\r`, 保证一条记录只占一行
+    Escape,
+    //续行以给定的字符串开头
+    Indent(String),
+    //续行重复输出%{message}之前的内容
+    Header,
+}
+
+impl Part {
+    fn literal(text: &str, offset: usize) -> Part {
+        Part::new("string".to_string(), Some(text.to_string()), None, offset)
+    }
+
+    fn new(name: String, layout: Option<String>, modifier: Option<Modifier>, offset: usize) -> Part {
+        let datetime = match name.as_str() {
+            "datetime" => Some(DateTimeLayout::parse(layout.as_ref().map(|l| l.as_str()))),
+            _ => None,
+        };
+        let condition = match name.as_str() {
+            "if" => layout.as_ref().and_then(|l| Condition::parse(l, offset)),
+            _ => None,
+        };
+        let style = match (name.as_str(), layout.as_ref().map(|l| l.as_str())) {
+            ("color", Some("level")) | ("level", Some("color")) => Some(PartStyle::Level),
+            ("color", Some(spec)) => level_color::parse_style(spec).map(PartStyle::Fixed),
+            _ => None,
+        };
+
+        Part {
+            name: name,
+            layout: layout,
+            modifier: modifier,
+            offset: offset,
+            datetime: datetime,
+            condition: condition,
+            children: Vec::new(),
+            custom: None,
+            style: style,
+        }
+    }
+}
+
+//自定义占位符的渲染函数, 参数为记录和`%{name:layout}`中的layout
+pub type Renderer = Fn(&LogEntry, Option<&str>, &mut fmt::Write) -> fmt::Result + Send + Sync;
+
+//应用注册的自定义占位符, 与内置占位符同名时覆盖内置的实现
+#[derive(Clone)]
+pub struct Placeholders {
+    renderers: HashMap<String, Arc<Renderer>>,
+}
+
+impl Placeholders {
+    pub fn new() -> Placeholders {
+        Placeholders {
+            renderers: HashMap::new(),
+        }
+    }
+
+    pub fn register<F>(&mut self, name: &str, renderer: F) -> &mut Self
+        where F: Fn(&LogEntry, Option<&str>, &mut fmt::Write) -> fmt::Result + Send + Sync + 'static
+    {
+        self.renderers.insert(name.to_string(), Arc::new(renderer));
+        self
+    }
+
+    fn get(&self, name: &str) -> Option<Arc<Renderer>> {
+        //"string"和区段名是保留的
+        if name == "string" || SECTIONS.contains(&name) {
+            return None;
+        }
+        self.renderers.get(name).cloned()
+    }
+
+    fn resolve(&self, parts: &mut [Part]) {
+        for part in parts {
+            part.custom = self.get(&part.name);
+            if let Some(Condition::Present { part: ref mut inner, .. }) = part.condition {
+                inner.custom = self.get(&inner.name);
+            }
+            self.resolve(&mut part.children);
+        }
+    }
+}
+
+//`%{if:...}`的条件:
+//`level=error|warn`, `level>=warn`(warn及更严重), `level<=debug`, `target`(非空), `!target`(为空)
+pub enum Condition {
+    Level(Vec<LogLevel>),
+    Present {
+        part: Box<Part>,
+        negate: bool,
+    },
+}
+
+impl Condition {
+    fn parse(condition: &str, offset: usize) -> Option<Condition> {
+        if condition.starts_with("level") {
+            let rest = &condition["level".len()..];
+            let (op, value) = if rest.starts_with(">=") || rest.starts_with("<=") {
+                (&rest[..2], &rest[2..])
+            } else if rest.starts_with('=') {
+                (&rest[..1], &rest[1..])
+            } else {
+                return None;
+            };
+
+            let mut levels = Vec::new();
+            for name in value.split('|') {
+                let level = match name.trim().parse::<LogLevel>() {
+                    Ok(level) => level,
+                    Err(_) => return None,
+                };
+                match op {
+                    ">=" => levels.extend(LOG_LEVELS.iter().filter(|l| **l <= level)),
+                    "<=" => levels.extend(LOG_LEVELS.iter().filter(|l| **l >= level)),
+                    _ => levels.push(level),
+                }
+            }
+            return Some(Condition::Level(levels));
+        }
+
+        let (negate, name) = if condition.starts_with('!') { (true, &condition[1..]) } else { (false, condition) };
+        if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return None;
+        }
+        Some(Condition::Present {
+            part: Box::new(Part::new(name.to_string(), None, None, offset)),
+            negate: negate,
+        })
+    }
+
+    #[inline]
+    fn matches(&self, args: &LogEntry, ctx: &Context) -> bool {
+        match *self {
+            Condition::Level(ref levels) => levels.contains(&args.level()),
+            Condition::Present { ref part, negate } => {
+                let plain = Context { color: false, style: Style::default(), multiline: None, header: &[], sanitize: SanitizeMode::Off, ..*ctx };
+                let mut value = String::new();
+                let _ = parse(part, args, &plain, &mut value);
+                value.is_empty() == negate
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LayoutErrorKind {
+    UnknownPlaceholder {
+        name: String,
+        suggestion: Option<String>,
+    },
+    MalformedPlaceholder,
+    UnbalancedSection {
+        name: String,
+    },
+    InvalidCondition(String),
+    InvalidStyle(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayoutError {
+    offset: usize,
+    kind: LayoutErrorKind,
+}
+
+impl LayoutError {
+    //出错位置在layout字符串中的字节偏移
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn kind(&self) -> &LayoutErrorKind {
+        &self.kind
+    }
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            LayoutErrorKind::UnknownPlaceholder { ref name, ref suggestion } => {
+                write!(f, "unknown placeholder `%{{{}}}` at offset {}", name, self.offset)?;
+                match suggestion {
+                    &Some(ref s) => write!(f, ", did you mean `%{{{}}}`?", s),
+                    &None => Ok(()),
+                }
+            }
+            LayoutErrorKind::MalformedPlaceholder =>
+                write!(f, "malformed placeholder at offset {}", self.offset),
+            LayoutErrorKind::UnbalancedSection { ref name } =>
+                write!(f, "unbalanced section `{}` at offset {}", name, self.offset),
+            LayoutErrorKind::InvalidCondition(ref condition) =>
+                write!(f, "invalid condition `{}` at offset {}", condition, self.offset),
+            LayoutErrorKind::InvalidStyle(ref style) =>
+                write!(f, "invalid style `{}` at offset {}", style, self.offset),
+        }
+    }
+}
+
+impl Error for LayoutError {
+    fn description(&self) -> &str {
+        match self.kind {
+            LayoutErrorKind::UnknownPlaceholder { .. } => "unknown placeholder",
+            LayoutErrorKind::MalformedPlaceholder => "malformed placeholder",
+            LayoutErrorKind::UnbalancedSection { .. } => "unbalanced section",
+            LayoutErrorKind::InvalidCondition(_) => "invalid condition",
+            LayoutErrorKind::InvalidStyle(_) => "invalid style",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Align {
+    Left,
+    Right,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Truncate {
+    //保留末尾的N个字符,与log4j的`%.30c`一致
+    Left(usize),
+    //保留开头的N个字符,即`%.-30c`
+    Right(usize),
+}
+
+//占位符的宽度修饰, 例如 `%{level:<5}`, `%{modulePath:>30.30}`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Modifier {
+    align: Align,
+    width: usize,
+    truncate: Option<Truncate>,
+}
+
+impl Modifier {
+    pub fn parse(spec: &str) -> Option<Modifier> {
+        let (align, rest) = match spec.chars().next() {
+            Some('<') => (Align::Left, &spec[1..]),
+            Some('>') => (Align::Right, &spec[1..]),
+            Some('.') => (Align::Left, spec),
+            _ => return None,
+        };
+
+        let (width, precision) = match rest.find('.') {
+            Some(i) => (&rest[..i], Some(&rest[i + 1..])),
+            None => (rest, None),
+        };
+
+        let width = if width.is_empty() {
+            0
+        } else {
+            match width.parse::<usize>() {
+                Ok(w) => w,
+                Err(_) => return None,
+            }
+        };
+
+        let truncate = match precision {
+            Some(p) => {
+                let (from_right, digits) = if p.starts_with('-') { (true, &p[1..]) } else { (false, p) };
+                match digits.parse::<usize>() {
+                    Ok(max) if from_right => Some(Truncate::Right(max)),
+                    Ok(max) => Some(Truncate::Left(max)),
+                    Err(_) => return None,
+                }
+            }
+            None => None,
+        };
+
+        Some(Modifier {
+            align: align,
+            width: width,
+            truncate: truncate,
+        })
+    }
+
+    pub fn write(&self, value: &str, buf: &mut fmt::Write) -> fmt::Result {
+        let mut len = value.chars().count();
+        let value = match self.truncate {
+            Some(Truncate::Left(max)) if len > max => {
+                let idx = value.char_indices().nth(len - max).map_or(value.len(), |(i, _)| i);
+                len = max;
+                &value[idx..]
+            }
+            Some(Truncate::Right(max)) if len > max => {
+                let idx = value.char_indices().nth(max).map_or(value.len(), |(i, _)| i);
+                len = max;
+                &value[..idx]
+            }
+            _ => value,
+        };
+
+        let pad = if self.width > len { self.width - len } else { 0 };
+        if self.align == Align::Right {
+            for _ in 0..pad {
+                buf.write_char(' ')?;
+            }
+        }
+        buf.write_str(value)?;
+        if self.align == Align::Left {
+            for _ in 0..pad {
+                buf.write_char(' ')?;
+            }
+        }
+        Ok(())
+    }
 }
 
 impl Parted for Part {
@@ -28,24 +405,231 @@ impl Parted for Part {
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Zone {
+    Local,
+    Utc,
+    //相对UTC的秒数, 东区为正
+    Fixed(i32),
+}
+
+impl Zone {
+    //`utc`, `local`, `+08:00`, `-0530`, `+08`
+    pub fn parse(token: &str) -> Option<Zone> {
+        match token {
+            "utc" | "UTC" | "Z" => return Some(Zone::Utc),
+            "local" => return Some(Zone::Local),
+            _ => {}
+        }
+
+        let sign = match token.chars().next() {
+            Some('+') => 1,
+            Some('-') => -1,
+            _ => return None,
+        };
+        let digits: String = token[1..].chars().filter(|c| *c != ':').collect();
+        if digits.chars().any(|c| !c.is_digit(10)) || token[1..].find(':').map_or(false, |i| i != 2) {
+            return None;
+        }
+        let (hours, minutes) = match digits.len() {
+            2 => (&digits[..], "0"),
+            4 => (&digits[..2], &digits[2..]),
+            _ => return None,
+        };
+        match (hours.parse::<i32>(), minutes.parse::<i32>()) {
+            (Ok(h), Ok(m)) if h < 24 && m < 60 => Some(Zone::Fixed(sign * (h * 3600 + m * 60))),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Precision {
+    Seconds,
+    Millis,
+    Micros,
+    Nanos,
+}
+
+impl Precision {
+    pub fn parse(token: &str) -> Option<Precision> {
+        match token {
+            "secs" | "seconds" => Some(Precision::Seconds),
+            "millis" => Some(Precision::Millis),
+            "micros" => Some(Precision::Micros),
+            "nanos" => Some(Precision::Nanos),
+            _ => None,
+        }
+    }
+
+    fn fraction(&self) -> &'static str {
+        match *self {
+            Precision::Seconds => "",
+            Precision::Millis => "%.3f",
+            Precision::Micros => "%.6f",
+            Precision::Nanos => "%.9f",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum DateTimeFormat {
+    Default,
+    Rfc2822,
+    Rfc3339,
+    EpochMillis,
+    Pattern(String),
+}
+
+//`%{datetime}`的参数,用`|`分隔格式和选项, 例如 `rfc3339|utc|millis`, `%Y-%m-%d %H:%M:%S|+08:00`, `epochMillis`
+#[derive(Clone, Debug, PartialEq)]
+pub struct DateTimeLayout {
+    zone: Zone,
+    format: DateTimeFormat,
+}
+
+impl DateTimeLayout {
+    pub fn parse(layout: Option<&str>) -> DateTimeLayout {
+        let mut zone = Zone::Local;
+        let mut precision = None;
+        let mut rest = Vec::new();
+
+        if let Some(layout) = layout {
+            for token in layout.split('|') {
+                match (Zone::parse(token), Precision::parse(token)) {
+                    (Some(z), _) => zone = z,
+                    (_, Some(p)) => precision = Some(p),
+                    _ => rest.push(token),
+                }
+            }
+        }
+
+        let format = match (rest.join("|").as_str(), precision) {
+            ("", None) => DateTimeFormat::Default,
+            ("", Some(p)) => DateTimeFormat::Pattern(format!("%Y-%m-%d %H:%M:%S{} %:z", p.fraction())),
+            ("rfc2822", _) => DateTimeFormat::Rfc2822,
+            ("rfc3339", None) => DateTimeFormat::Rfc3339,
+            ("rfc3339", Some(p)) => DateTimeFormat::Pattern(format!("%Y-%m-%dT%H:%M:%S{}%:z", p.fraction())),
+            ("epochMillis", _) => DateTimeFormat::EpochMillis,
+            //自定义格式后面加上小数部分, 例如`%H:%M:%S|millis`
+            (pattern, Some(p)) => DateTimeFormat::Pattern(format!("{}{}", pattern, p.fraction())),
+            (pattern, None) => DateTimeFormat::Pattern(pattern.to_string()),
+        };
+
+        DateTimeLayout {
+            zone: zone,
+            format: format,
+        }
+    }
+
+    pub fn write(&self, ts: Timespec, buf: &mut fmt::Write) -> fmt::Result {
+        if self.format == DateTimeFormat::EpochMillis {
+            return write!(buf, "{}", ts.sec * 1000 + (ts.nsec / 1_000_000) as i64);
+        }
+
+        let now = get_record_date_time(ts, self.zone);
+        match self.format {
+            DateTimeFormat::Rfc2822 =>
+                write!(buf, "{}", now.format_with_items(RFC2822_ITEMS.iter().cloned())),
+            DateTimeFormat::Rfc3339 =>
+                write!(buf, "{}", now.format_with_items(RFC3339_ITEMS.iter().cloned())),
+            DateTimeFormat::Pattern(ref pattern) =>
+                write!(buf, "{}", now.format(pattern)),
+            _ =>
+                write!(buf, "{}", now),
+        }
+    }
+}
+
 pub struct StringFormatter {
     parts: Vec<Part>,
+    palette: Palette,
+    //layout中有`%{color:...}`时不再给整行着色
+    part_colors: bool,
+    multiline: Multiline,
+    sanitize: Sanitize,
 }
 
 impl StringFormatter {
     #[inline]
     pub fn new(layout: &str) -> StringFormatter {
-        let parts = StringFormatter::parse_parts(layout);
+        let parts = StringFormatter::parse_parts(layout, &mut Vec::new());
         StringFormatter {
+            part_colors: has_part_colors(&parts),
             parts: parts,
+            palette: Palette::new(),
+            multiline: Multiline::Raw,
+            sanitize: Sanitize::Auto,
+        }
+    }
+
+    //与new相同,但遇到未知的占位符、不完整的`%{`或不配对的区段时返回错误
+    pub fn try_new(layout: &str) -> Result<StringFormatter, LayoutError> {
+        StringFormatterBuilder::new(layout).build()
+    }
+
+    fn validate(parts: &[Part], placeholders: &Placeholders, errors: &mut Vec<LayoutError>) {
+        for part in parts {
+            StringFormatter::validate_part(part, placeholders, errors);
+        }
+    }
+
+    fn validate_part(part: &Part, placeholders: &Placeholders, errors: &mut Vec<LayoutError>) {
+        if part.custom.is_some() {
+            return;
+        }
+        match part.name() {
+            "string" => {
+                if let &Some(ref literal) = part.layout() {
+                    if let Some(i) = literal.find("%{") {
+                        errors.push(LayoutError {
+                            offset: part.offset + i,
+                            kind: LayoutErrorKind::MalformedPlaceholder,
+                        });
+                    }
+                }
+            }
+            "if" => {
+                match part.condition {
+                    Some(Condition::Present { part: ref inner, .. }) =>
+                        StringFormatter::validate_part(inner, placeholders, errors),
+                    Some(Condition::Level(_)) => {}
+                    None => errors.push(LayoutError {
+                        offset: part.offset,
+                        kind: LayoutErrorKind::InvalidCondition(part.layout().clone().unwrap_or_default()),
+                    }),
+                }
+                StringFormatter::validate(&part.children, placeholders, errors);
+            }
+            "color" => {
+                if part.style.is_none() {
+                    errors.push(LayoutError {
+                        offset: part.offset,
+                        kind: LayoutErrorKind::InvalidStyle(part.layout().clone().unwrap_or_default()),
+                    });
+                }
+                StringFormatter::validate(&part.children, placeholders, errors);
+            }
+            name if PLACEHOLDERS.contains(&name) => {}
+            name => {
+                errors.push(LayoutError {
+                    offset: part.offset,
+                    kind: LayoutErrorKind::UnknownPlaceholder {
+                        name: name.to_string(),
+                        suggestion: suggest(name, placeholders),
+                    },
+                });
+            }
         }
     }
 
     #[inline]
-    fn parse_parts(layout: &str) -> Vec<Part> {
-        let regex = Regex::new(r"%\{([a-zA-Z]+)(?::(.*?[^\\]))?\}").unwrap();
+    fn parse_parts(layout: &str, errors: &mut Vec<LayoutError>) -> Vec<Part> {
+        let regex = Regex::new(r"%\{(/?[a-zA-Z_][a-zA-Z0-9_]*)(?::(.*?[^\\]))?\}").unwrap();
 
         let mut parts = Vec::new();
+        //尚未闭合的区段, 占位符先放进最内层区段的children
+        let mut sections: Vec<Part> = Vec::new();
 
         let mut prev = 0usize;
 
@@ -55,115 +639,409 @@ impl StringFormatter {
             let (start, end) = (pos.start(), pos.end());
             if start > prev {
                 //prev have string
-                let substrings = layout[prev..start].to_string().clone();
-
-                parts.push(Part {
-                    name: "string".to_string(),
-                    layout: Some(substrings)
-                });
+                current_parts(&mut sections, &mut parts).push(Part::literal(&layout[prev..start], prev));
             };
 
             let substrings = layout[start + 2..end - 1].to_string();
             let mut split: Vec<String> = substrings.split(":").map(String::from).collect();
-            let name = split[0].clone();
+            let name = split.remove(0);
+            //最后一段如果是宽度修饰(`<5`, `>30.30`, `.-10`),则从layout中剥离
+            let modifier = split.last().and_then(|last| Modifier::parse(last));
+            if modifier.is_some() {
+                split.pop();
+            }
             let layout = match split.len() {
-                2 => Some(split[1].clone()),
-                0 | 1 => None,
-                _ => {
-                    split.remove(0);
-                    Some(split.connect(":"))
-                }
+                0 => None,
+                _ => Some(split.join(":")),
             };
 
-
-            parts.push(Part {
-                name: name,
-                layout: layout,
-            });
-
+            if name.starts_with('/') {
+                match sections.last().map(|section| section.name == name[1..]) {
+                    Some(true) => {
+                        let section = sections.pop().unwrap();
+                        current_parts(&mut sections, &mut parts).push(section);
+                    }
+                    _ => errors.push(LayoutError {
+                        offset: start,
+                        kind: LayoutErrorKind::UnbalancedSection { name: name[1..].to_string() },
+                    }),
+                }
+            } else if SECTIONS.contains(&name.as_str()) {
+                sections.push(Part::new(name, layout, modifier, start));
+            } else {
+                current_parts(&mut sections, &mut parts).push(Part::new(name, layout, modifier, start));
+            }
 
             prev = end;
         };
 
         if prev < layout.len() {
-            let substrings = layout[prev..layout.len()].to_string().clone();
-            parts.push(Part {
-                name: "string".to_string(),
-                layout: Some(substrings)
-            })
+            current_parts(&mut sections, &mut parts).push(Part::literal(&layout[prev..layout.len()], prev));
+        }
+
+        //未闭合的区段视为在末尾闭合
+        while let Some(section) = sections.pop() {
+            errors.push(LayoutError {
+                offset: section.offset,
+                kind: LayoutErrorKind::UnbalancedSection { name: section.name.clone() },
+            });
+            current_parts(&mut sections, &mut parts).push(section);
         }
         return parts;
     }
 }
 
+pub struct StringFormatterBuilder {
+    layout: String,
+    placeholders: Placeholders,
+    palette: Palette,
+    multiline: Multiline,
+    sanitize: Sanitize,
+}
+
+impl StringFormatterBuilder {
+    pub fn new(layout: &str) -> StringFormatterBuilder {
+        StringFormatterBuilder {
+            layout: layout.to_string(),
+            placeholders: Placeholders::new(),
+            palette: Palette::new(),
+            multiline: Multiline::Raw,
+            sanitize: Sanitize::Auto,
+        }
+    }
+
+    pub fn placeholders(&mut self, placeholders: &Placeholders) -> &mut Self {
+        for (name, renderer) in &placeholders.renderers {
+            self.placeholders.renderers.insert(name.clone(), renderer.clone());
+        }
+        self
+    }
+
+    pub fn placeholder<F>(&mut self, name: &str, renderer: F) -> &mut Self
+        where F: Fn(&LogEntry, Option<&str>, &mut fmt::Write) -> fmt::Result + Send + Sync + 'static
+    {
+        self.placeholders.register(name, renderer);
+        self
+    }
+
+    //整行着色和`%{color:level}`使用的调色板
+    pub fn palette(&mut self, palette: Palette) -> &mut Self {
+        self.palette = palette;
+        self
+    }
+
+    pub fn multiline(&mut self, multiline: Multiline) -> &mut Self {
+        self.multiline = multiline;
+        self
+    }
+
+    pub fn sanitize(&mut self, sanitize: Sanitize) -> &mut Self {
+        self.sanitize = sanitize;
+        self
+    }
+
+    pub fn build(&mut self) -> Result<StringFormatter, LayoutError> {
+        let mut errors = Vec::new();
+        let mut parts = StringFormatter::parse_parts(&self.layout, &mut errors);
+        self.placeholders.resolve(&mut parts);
+        StringFormatter::validate(&parts, &self.placeholders, &mut errors);
+
+        match errors.into_iter().min_by_key(|e| e.offset) {
+            Some(err) => Err(err),
+            None => Ok(StringFormatter {
+                part_colors: has_part_colors(&parts),
+                parts: parts,
+                palette: self.palette,
+                multiline: self.multiline.clone(),
+                sanitize: self.sanitize,
+            }),
+        }
+    }
+}
+
+fn has_part_colors(parts: &[Part]) -> bool {
+    parts.iter().any(|part| part.style.is_some() || has_part_colors(&part.children))
+}
+
+#[inline]
+fn current_parts<'a>(sections: &'a mut Vec<Part>, parts: &'a mut Vec<Part>) -> &'a mut Vec<Part> {
+    match sections.last_mut() {
+        Some(section) => &mut section.children,
+        None => parts,
+    }
+}
+
 
 impl Formatter for StringFormatter {
     #[inline]
-    fn parse(&self, color: bool, record: &LogEntry) -> String
+    fn format(&self, color: bool, sanitize: SanitizeMode, record: &LogEntry, buf: &mut fmt::Write) -> fmt::Result
     {
-        let mut res = String::with_capacity(100);
-        for part in &self.parts {
-            res += &parse(part, record);
+        let mut ctx = Context {
+            color: color,
+            palette: &self.palette,
+            style: Style::default(),
+            multiline: Some(&self.multiline),
+            header: &[],
+            sanitize: match self.sanitize {
+                Sanitize::Auto => sanitize,
+                Sanitize::Always => SanitizeMode::Full,
+                Sanitize::Never => SanitizeMode::Off,
+            },
+        };
+        if color && !self.part_colors {
+            ctx.style = self.palette.style(record.level());
+            write!(buf, "{}", ctx.style.prefix())?;
+            for (i, part) in self.parts.iter().enumerate() {
+                write_part(part, record, &Context { header: &self.parts[..i], ..ctx }, buf)?;
+            }
+            return write!(buf, "{}", ctx.style.suffix());
+        }
+        for (i, part) in self.parts.iter().enumerate() {
+            write_part(part, record, &Context { header: &self.parts[..i], ..ctx }, buf)?;
+        }
+        Ok(())
+    }
+}
+
+#[inline]
+fn write_part(part: &Part, args: &LogEntry, ctx: &Context, buf: &mut fmt::Write) -> fmt::Result {
+    match part.style {
+        Some(ref part_style) if ctx.color => {
+            let style = part_style.resolve(ctx.palette, args.level());
+            write!(buf, "{}", style.prefix())?;
+            write_modified(part, args, &Context { style: style, ..*ctx }, buf)?;
+            //恢复外层的样式
+            write!(buf, "{}{}", style.suffix(), ctx.style.prefix())
         }
-        if color{
-            return level_color::get_color_by_level(record.level(), res.as_str())
+        _ => write_modified(part, args, ctx, buf),
+    }
+}
+
+#[inline]
+fn write_modified(part: &Part, args: &LogEntry, ctx: &Context, buf: &mut fmt::Write) -> fmt::Result {
+    match part.modifier {
+        Some(ref modifier) => {
+            //需要知道渲染后的宽度,只有带修饰的占位符才经过临时字符串
+            let mut value = String::with_capacity(32);
+            parse(part, args, ctx, &mut value)?;
+            modifier.write(&value, buf)
         }
-        res
+        None => parse(part, args, ctx, buf),
     }
 }
 
 #[inline]
-fn parse(part: &Part, args: &LogEntry) -> String {
+fn parse(part: &Part, args: &LogEntry, ctx: &Context, buf: &mut fmt::Write) -> fmt::Result {
+    if let Some(ref renderer) = part.custom {
+        let layout = part.layout().as_ref().map(|l| l.as_str());
+        return match ctx.sanitize {
+            SanitizeMode::Off => renderer(args, layout, buf),
+            SanitizeMode::Ansi => renderer(args, layout, &mut Sanitizer::keep_newlines(buf)),
+            SanitizeMode::Full => renderer(args, layout, &mut Sanitizer::new(buf)),
+        };
+    }
     match part.name() {
         "string" => {
             match part.layout() {
                 &Some(ref layout) =>
-                    return layout.clone(),
+                    buf.write_str(layout),
                 _ =>
-                    return "".to_string(),
-            };
+                    Ok(()),
+            }
+        }
+        "if" => {
+            match part.condition {
+                Some(ref condition) if condition.matches(args, ctx) => {
+                    for child in &part.children {
+                        write_part(child, args, ctx, buf)?;
+                    }
+                    Ok(())
+                }
+                _ => Ok(()),
+            }
+        }
+        "color" => {
+            for child in &part.children {
+                write_part(child, args, ctx, buf)?;
+            }
+            Ok(())
         }
         "datetime" => {
-            let now = get_record_date_time(args.time);
-            match part.layout() {
-                &Some(ref layout) =>
-                    match layout.as_str() {
-                        "rfc2822" =>
-                            return now.to_rfc2822(),
-                        "rfc3339" =>
-                            return now.to_rfc3339(),
-                        _ =>
-                            return now.format(layout).to_string(),
-                    },
-                _ =>
-                    return now.to_string(),
-            };
+            match part.datetime {
+                Some(ref datetime) => datetime.write(args.time, buf),
+                None => Ok(()),
+            }
         }
         "line" => {
-            return format!("{}", args.location().line());
+            write!(buf, "{}", args.location().line())
         }
         "level" => {
-            return format!("{}", args.level());
+            write!(buf, "{}", args.level())
         }
 
         "file" => {
-            return format!("{}", args.location().file());
+            buf.write_str(args.location().file())
         }
         "modulePath" => {
-            return format!("{}", args.location().module_path());
+            buf.write_str(args.location().module_path())
         }
         "message" => {
-            return format!("{}", args.args());
+            write_message(args.args(), args, ctx, buf)
+        }
+        "thread" => {
+            write_field(args.thread_name().unwrap_or("<unnamed>"), ctx, buf)
+        }
+        "threadId" => {
+            write!(buf, "{}", args.thread_id())
+        }
+        "target" => {
+            write_field(args.target(), ctx, buf)
+        }
+        "pid" => {
+            write!(buf, "{}", args.process().pid())
+        }
+        "hostname" => {
+            buf.write_str(args.process().hostname())
+        }
+        "appName" => {
+            buf.write_str(args.process().app_name())
+        }
+        "seq" => {
+            write!(buf, "{}", args.seq())
+        }
+        "elapsed" => {
+            write_duration(args.time() - args.process().init_time(), part.layout(), buf)
+        }
+        "delta" => {
+            write_duration(args.delta(), part.layout(), buf)
+        }
+        "uptime" => {
+            write_duration(args.time() - args.process().start_time(), part.layout(), buf)
+        }
+
+        _ => {
+            Ok(())
+        }
+    }
+}
+
+//忽略大小写和下划线后,找编辑距离最近的已知占位符
+fn suggest(name: &str, placeholders: &Placeholders) -> Option<String> {
+    let normalize = |s: &str| -> Vec<char> {
+        s.chars().filter(|c| *c != '_' && *c != '-').flat_map(|c| c.to_lowercase()).collect()
+    };
+    let wanted = normalize(name);
+    let threshold = ::std::cmp::max(1, wanted.len() / 3);
+
+    PLACEHOLDERS.iter()
+        .map(|known| *known)
+        .chain(placeholders.renderers.keys().map(|known| known.as_str()))
+        .map(|known| (edit_distance(&wanted, &normalize(known)), known))
+        .filter(|&(distance, _)| distance <= threshold)
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, known)| known.to_string())
+}
+
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut prev: Vec<usize> = (0..b.len() + 1).collect();
+    let mut current = vec![0; b.len() + 1];
+    for i in 0..a.len() {
+        current[0] = i + 1;
+        for j in 0..b.len() {
+            let cost = if a[i] == b[j] { 0 } else { 1 };
+            current[j + 1] = ::std::cmp::min(::std::cmp::min(prev[j + 1] + 1, current[j] + 1), prev[j] + cost);
+        }
+        ::std::mem::swap(&mut prev, &mut current);
+    }
+    prev[b.len()]
+}
+
+#[inline]
+fn write_field(value: &str, ctx: &Context, buf: &mut fmt::Write) -> fmt::Result {
+    match ctx.sanitize {
+        SanitizeMode::Off => buf.write_str(value),
+        SanitizeMode::Ansi => Sanitizer::keep_newlines(buf).write_str(value),
+        SanitizeMode::Full => Sanitizer::new(buf).write_str(value),
+    }
+}
+
+#[inline]
+fn write_message(msg: &str, args: &LogEntry, ctx: &Context, buf: &mut fmt::Write) -> fmt::Result {
+    if msg.find(|c| c == '\n' || c == '\r').is_none() {
+        return write_field(msg, ctx, buf);
+    }
+
+    match ctx.multiline {
+        Some(&Multiline::Escape) if ctx.sanitize == SanitizeMode::Off => {
+            for c in msg.chars() {
+                match c {
+                    '\n' => buf.write_str("\\n")?,
+                    '\r' => buf.write_str("\\r")?,
+                    c => buf.write_char(c)?,
+                }
+            }
+            Ok(())
+        }
+        //要转义换行时终端上也按SanitizeMode::Full处理, 其余控制字符和ANSI序列同样去掉
+        Some(&Multiline::Escape) => Sanitizer::new(buf).write_str(msg),
+        Some(&Multiline::Indent(ref indent)) => {
+            for (i, line) in msg.split('\n').enumerate() {
+                if i > 0 {
+                    buf.write_char('\n')?;
+                    buf.write_str(indent)?;
+                }
+                write_field(line.trim_right_matches('\r'), ctx, buf)?;
+            }
+            Ok(())
         }
+        Some(&Multiline::Header) => {
+            //重复header时不再处理其中的换行, 避免递归
+            let header_ctx = Context { multiline: None, header: &[], ..*ctx };
+            for (i, line) in msg.split('\n').enumerate() {
+                if i > 0 {
+                    buf.write_char('\n')?;
+                    for part in ctx.header {
+                        write_part(part, args, &header_ctx, buf)?;
+                    }
+                }
+                write_field(line.trim_right_matches('\r'), ctx, buf)?;
+            }
+            Ok(())
+        }
+        _ => write_field(msg, ctx, buf),
+    }
+}
 
+//单位由layout指定: `ms`(默认), `us`, `s`(保留三位小数)
+#[inline]
+fn write_duration(duration: Duration, unit: &Option<String>, buf: &mut fmt::Write) -> fmt::Result {
+    match unit.as_ref().map(|u| u.as_str()) {
+        Some("s") => {
+            let ms = duration.num_milliseconds();
+            let sign = if ms < 0 { "-" } else { "" };
+            write!(buf, "{}{}.{:03}", sign, ms.abs() / 1000, ms.abs() % 1000)
+        }
+        Some("us") => {
+            write!(buf, "{}", duration.num_microseconds().unwrap_or(i64::max_value()))
+        }
         _ => {
-            return format!("{}", "");
+            write!(buf, "{}", duration.num_milliseconds())
         }
     }
 }
 
 #[inline]
-fn get_record_date_time(ts: Timespec) -> DateTime<Local> {
+pub fn get_record_date_time(ts: Timespec, zone: Zone) -> DateTime<FixedOffset> {
+    let secs = match zone {
+        Zone::Local => return get_local_date_time(ts),
+        Zone::Utc => 0,
+        Zone::Fixed(secs) => secs,
+    };
+    DateTime::from_utc(NaiveDateTime::from_timestamp(ts.sec, ts.nsec as u32), FixedOffset::east(secs))
+}
+
+#[inline]
+fn get_local_date_time(ts: Timespec) -> DateTime<FixedOffset> {
     let mut tm = time::at(ts);
 
     if tm.tm_sec >= 60 {
@@ -189,3 +1067,297 @@ fn get_record_date_time(ts: Timespec) -> DateTime<Local> {
     let offset = FixedOffset::east(tm.tm_utcoff);
     DateTime::from_utc(date.and_time(time) - offset, offset)
 }
+
+#[cfg(test)]
+pub fn test_entry(level: ::log::LogLevel, module_path: &'static str, file: &'static str, line: u32, msg: &str) -> LogEntry {
+    use log::LogLocation;
+    use std::thread;
+
+    LogEntry {
+        location: LogLocation {
+            __module_path: module_path,
+            __file: file,
+            __line: line,
+        },
+        msg: msg.to_string(),
+        level: level,
+        time: time::get_time(),
+        thread_id: ::current_thread_id(),
+        thread_name: thread::current().name().map(String::from),
+        target: module_path.to_string(),
+        process: ::std::sync::Arc::new(::ProcessInfo::capture(Some("intro-test"))),
+        seq: line as usize,
+        delta: Duration::zero(),
+    }
+}
+
+#[test]
+fn format_into_buffer() {
+    use log::LogLevel;
+
+    let entry = test_entry(LogLevel::Info, "intro::format", "src/format.rs", 7, "hello");
+    let formatter = StringFormatter::new("%{level} %{modulePath}:%{line} %{message}");
+
+    let mut buf = String::new();
+    formatter.format(false, SanitizeMode::Off, &entry, &mut buf).unwrap();
+    formatter.format(false, SanitizeMode::Off, &entry, &mut buf).unwrap();
+    assert_eq!(buf, "INFO intro::format:7 helloINFO intro::format:7 hello");
+    assert_eq!(formatter.parse(false, &entry), "INFO intro::format:7 hello");
+}
+
+#[test]
+fn format_width_modifiers() {
+    use log::LogLevel;
+
+    let entry = test_entry(LogLevel::Warn, "intro::channel::single_channel", "src/channel/single_channel.rs", 42, "hello");
+
+    let formatter = StringFormatter::new("[%{level:<5}][%{line:>4}][%{modulePath:>16.16}][%{modulePath:.-5}]");
+    assert_eq!(formatter.parse(false, &entry), "[WARN ][  42][::single_channel][intro]");
+
+    assert_eq!(Modifier::parse("30"), None);
+    assert_eq!(Modifier::parse("%S"), None);
+    let formatter = StringFormatter::new("%{datetime:%H:%M:%S:>10}|");
+    assert_eq!(formatter.parse(false, &entry).len(), 11);
+}
+
+#[test]
+fn layout_errors() {
+    let err = StringFormatter::try_new("%{level}\t%{module_path}\t%{message}").err().unwrap();
+    assert_eq!(err.offset(), 9);
+    assert_eq!(err.kind(), &LayoutErrorKind::UnknownPlaceholder {
+        name: "module_path".to_string(),
+        suggestion: Some("modulePath".to_string()),
+    });
+
+    let err = StringFormatter::try_new("%{level} %{mesage").err().unwrap();
+    assert_eq!(err.offset(), 9);
+    assert_eq!(err.kind(), &LayoutErrorKind::MalformedPlaceholder);
+
+    let err = StringFormatter::try_new("%{nothingLikeIt}").err().unwrap();
+    assert_eq!(err.to_string(), "unknown placeholder `%{nothingLikeIt}` at offset 0");
+
+    assert!(StringFormatter::try_new("%{datetime:%H:%M:%S} %{level:<5} %{message}").is_ok());
+}
+
+#[test]
+fn format_thread() {
+    use log::LogLevel;
+    use std::thread;
+
+    let formatter = StringFormatter::new("%{thread}/%{threadId}");
+
+    let named = thread::Builder::new().name("worker-1".to_string()).spawn(|| {
+        test_entry(LogLevel::Info, "intro", "src/lib.rs", 1, "")
+    }).unwrap().join().unwrap();
+    let unnamed = thread::spawn(|| test_entry(LogLevel::Info, "intro", "src/lib.rs", 1, "")).join().unwrap();
+
+    assert!(named.thread_id() != unnamed.thread_id());
+    assert_eq!(formatter.parse(false, &named), format!("worker-1/{}", named.thread_id()));
+    assert_eq!(formatter.parse(false, &unnamed), format!("<unnamed>/{}", unnamed.thread_id()));
+}
+
+#[test]
+fn format_target() {
+    use log::LogLevel;
+
+    let mut entry = test_entry(LogLevel::Info, "intro::format", "src/format.rs", 7, "login");
+    let formatter = StringFormatter::new("%{target}: %{message}");
+    assert_eq!(formatter.parse(false, &entry), "intro::format: login");
+
+    entry.target = "audit".to_string();
+    assert_eq!(formatter.parse(false, &entry), "audit: login");
+}
+
+#[test]
+fn format_process() {
+    use log::LogLevel;
+
+    let entry = test_entry(LogLevel::Info, "intro::format", "src/format.rs", 3, "");
+    let formatter = StringFormatter::try_new("%{appName}[%{pid}]@%{hostname} #%{seq}").unwrap();
+    assert_eq!(formatter.parse(false, &entry),
+               format!("intro-test[{}]@{} #3", entry.process().pid(), entry.process().hostname()));
+    assert!(entry.process().pid() > 0);
+    assert!(!entry.process().hostname().is_empty());
+}
+
+#[test]
+fn format_datetime_zones() {
+    use log::LogLevel;
+
+    let mut entry = test_entry(LogLevel::Info, "intro::format", "src/format.rs", 1, "");
+    entry.time = Timespec::new(1_500_000_000, 123_456_789);
+
+    let cases = [
+        ("%{datetime:rfc3339|utc|millis}", "2017-07-14T02:40:00.123+00:00"),
+        ("%{datetime:rfc3339|utc}", "2017-07-14T02:40:00.123456789+00:00"),
+        ("%{datetime:%Y-%m-%d %H:%M|+08:00}", "2017-07-14 10:40"),
+        ("%{datetime:%H:%M|-0530}", "21:10"),
+        ("%{datetime:%H:%M:%S|utc|millis}", "02:40:00.123"),
+        ("%{datetime:utc|secs}", "2017-07-14 02:40:00 +00:00"),
+        ("%{datetime:rfc3339|+08:00|micros}", "2017-07-14T10:40:00.123456+08:00"),
+        ("%{datetime:epochMillis}", "1500000000123"),
+    ];
+    for &(layout, expected) in cases.iter() {
+        assert_eq!(StringFormatter::new(layout).parse(false, &entry), expected, "{}", layout);
+    }
+
+    assert_eq!(Zone::parse("+8:00"), None);
+    assert_eq!(Zone::parse("+%H"), None);
+}
+
+#[test]
+fn format_elapsed() {
+    use log::LogLevel;
+
+    let mut entry = test_entry(LogLevel::Info, "intro::format", "src/format.rs", 1, "");
+    entry.time = entry.process().init_time() + Duration::milliseconds(1234);
+    entry.delta = Duration::microseconds(2500);
+
+    let formatter = StringFormatter::try_new("%{elapsed} %{elapsed:s} %{delta:us} %{delta}").unwrap();
+    assert_eq!(formatter.parse(false, &entry), "1234 1.234 2500 2");
+    assert!(entry.process().start_time() <= entry.process().init_time());
+
+    let uptime = StringFormatter::new("%{uptime}").parse(false, &entry);
+    assert!(uptime.parse::<i64>().unwrap() >= 1234);
+}
+
+#[test]
+fn format_sections() {
+    use log::LogLevel;
+
+    let formatter = StringFormatter::try_new("%{level:<5} %{message}%{if:level>=warn} (%{file}:%{line})%{/if}%{if:target} [%{target}]%{/if}").unwrap();
+
+    let mut entry = test_entry(LogLevel::Info, "intro::format", "src/format.rs", 7, "started");
+    assert_eq!(formatter.parse(false, &entry), "INFO  started [intro::format]");
+
+    entry.level = LogLevel::Error;
+    entry.target = String::new();
+    assert_eq!(formatter.parse(false, &entry), "ERROR started (src/format.rs:7)");
+
+    let formatter = StringFormatter::new("%{if:level=debug|trace}<%{if:!target}no target%{/if}>%{/if}");
+    entry.level = LogLevel::Trace;
+    assert_eq!(formatter.parse(false, &entry), "<no target>");
+    entry.level = LogLevel::Warn;
+    assert_eq!(formatter.parse(false, &entry), "");
+
+    let err = StringFormatter::try_new("%{if:level>=warn}%{file}").err().unwrap();
+    assert_eq!((err.offset(), err.kind()), (0, &LayoutErrorKind::UnbalancedSection { name: "if".to_string() }));
+    let err = StringFormatter::try_new("%{message}%{/if}").err().unwrap();
+    assert_eq!(err.offset(), 10);
+    let err = StringFormatter::try_new("%{if:level>=loud}x%{/if}").err().unwrap();
+    assert_eq!(err.kind(), &LayoutErrorKind::InvalidCondition("level>=loud".to_string()));
+    let err = StringFormatter::try_new("%{if:modul_path}x%{/if}").err().unwrap();
+    assert_eq!(err.to_string(), "unknown placeholder `%{modul_path}` at offset 0, did you mean `%{modulePath}`?");
+}
+
+#[test]
+fn format_custom_placeholders() {
+    use log::LogLevel;
+
+    let mut placeholders = Placeholders::new();
+    placeholders.register("requestId", |entry, _, buf| {
+        //示例: 从消息里的`rid=`取出请求编号
+        match entry.args().split_whitespace().find(|w| w.starts_with("rid=")) {
+            Some(word) => buf.write_str(&word[4..]),
+            None => Ok(()),
+        }
+    });
+
+    let formatter = StringFormatterBuilder::new("%{if:requestId}[%{requestId:>6}] %{/if}%{tenant:acme}|%{message}")
+        .placeholders(&placeholders)
+        .placeholder("tenant", |_, layout, buf| buf.write_str(layout.unwrap_or("-")))
+        .build()
+        .unwrap();
+
+    let entry = test_entry(LogLevel::Info, "intro::format", "src/format.rs", 1, "served rid=a1b2");
+    assert_eq!(formatter.parse(false, &entry), "[  a1b2] acme|served rid=a1b2");
+    let entry = test_entry(LogLevel::Info, "intro::format", "src/format.rs", 1, "idle");
+    assert_eq!(formatter.parse(false, &entry), "acme|idle");
+
+    let err = StringFormatterBuilder::new("%{request_id}").placeholders(&placeholders).build().err().unwrap();
+    assert_eq!(err.to_string(), "unknown placeholder `%{request_id}` at offset 0, did you mean `%{requestId}`?");
+}
+
+#[test]
+fn format_part_colors() {
+    use log::LogLevel;
+    use level_color::{Colour, parse_style};
+
+    let mut palette = Palette::new();
+    palette.set(LogLevel::Info, parse_style("bold #00ff00").unwrap());
+
+    let formatter = StringFormatterBuilder::new("%{level:color:<5} %{color:dimmed}%{modulePath} %{color:208}[%{line}]%{/color}%{/color} %{message}")
+        .palette(palette)
+        .build()
+        .unwrap();
+    let entry = test_entry(LogLevel::Info, "intro::format", "src/format.rs", 7, "hello");
+
+    let level = Colour::RGB(0, 255, 0).bold();
+    let module = Style::default().dimmed();
+    let line = Colour::Fixed(208).normal();
+    assert_eq!(formatter.parse(true, &entry),
+               format!("{}INFO {}{} {}intro::format {}[7]{}{}{}{} hello",
+                       level.prefix(), level.suffix(), Style::default().prefix(),
+                       module.prefix(), line.prefix(), line.suffix(), module.prefix(), module.suffix(), Style::default().prefix()));
+    assert_eq!(formatter.parse(false, &entry), "INFO  intro::format [7] hello");
+
+    //没有区段时整行按调色板着色
+    let entry = test_entry(LogLevel::Error, "intro::format", "src/format.rs", 7, "boom");
+    assert_eq!(StringFormatter::new("%{message}").parse(true, &entry), Colour::Red.paint("boom").to_string());
+
+    let err = StringFormatter::try_new("%{color:rainbow}x%{/color}").err().unwrap();
+    assert_eq!(err.kind(), &LayoutErrorKind::InvalidStyle("rainbow".to_string()));
+    let err = StringFormatter::try_new("%{color:#aébcd}x%{/color}").err().unwrap();
+    assert_eq!(err.kind(), &LayoutErrorKind::InvalidStyle("#aébcd".to_string()));
+}
+
+#[test]
+fn format_multiline() {
+    use log::LogLevel;
+
+    let entry = test_entry(LogLevel::Warn, "intro::format", "src/format.rs", 7, "panicked\r\n  at main.rs\n  at lib.rs");
+    let layout = "%{level:<5} %{target}: %{message} (%{line})";
+    let build = |multiline: Multiline| StringFormatterBuilder::new(layout).multiline(multiline).build().unwrap();
+
+    assert_eq!(build(Multiline::Raw).parse(false, &entry),
+               "WARN  intro::format: panicked\r\n  at main.rs\n  at lib.rs (7)");
+    assert_eq!(build(Multiline::Escape).parse(false, &entry),
+               "WARN  intro::format: panicked\\r\\n  at main.rs\\n  at lib.rs (7)");
+    assert_eq!(build(Multiline::Indent("\t".to_string())).parse(false, &entry),
+               "WARN  intro::format: panicked\n\t  at main.rs\n\t  at lib.rs (7)");
+    assert_eq!(build(Multiline::Header).parse(false, &entry),
+               "WARN  intro::format: panicked\nWARN  intro::format:   at main.rs\nWARN  intro::format:   at lib.rs (7)");
+}
+
+#[test]
+fn format_sanitize() {
+    use log::LogLevel;
+
+    let mut entry = test_entry(LogLevel::Info, "intro::format", "src/format.rs", 1, "user=bob\n2017-07-14 ERROR \u{1b}[31mforged");
+    entry.target = "audit\r".to_string();
+
+    let formatter = StringFormatter::new("%{target}: %{message}");
+    let mut buf = String::new();
+    formatter.format(false, SanitizeMode::Full, &entry, &mut buf).unwrap();
+    assert_eq!(buf, "audit\\r: user=bob\\n2017-07-14 ERROR forged");
+    buf.clear();
+    formatter.format(false, SanitizeMode::Ansi, &entry, &mut buf).unwrap();
+    assert_eq!(buf, "audit\\r: user=bob\n2017-07-14 ERROR forged");
+    assert_eq!(formatter.parse(false, &entry), "audit\r: user=bob\n2017-07-14 ERROR \u{1b}[31mforged");
+
+    let formatter = StringFormatterBuilder::new("%{message}")
+        .sanitize(Sanitize::Always)
+        .multiline(Multiline::Indent("  ".to_string()))
+        .build()
+        .unwrap();
+    assert_eq!(formatter.parse(false, &entry), "user=bob\n  2017-07-14 ERROR forged");
+
+    let entry = test_entry(LogLevel::Info, "intro::format", "src/format.rs", 1, "a\n\u{1b}[2Jb\u{7}");
+    let formatter = StringFormatterBuilder::new("%{message}")
+        .multiline(Multiline::Escape)
+        .build()
+        .unwrap();
+    let mut buf = String::new();
+    formatter.format(false, SanitizeMode::Ansi, &entry, &mut buf).unwrap();
+    assert_eq!(buf, "a\\nb\\x07");
+}
@@ -3,19 +3,120 @@ use log::{LogLevel, LogLevelFilter, LogLocation, SetLoggerError, LogMetadata, Lo
 use std::collections::HashMap;
 use std::hash::Hash;
 use std::cell::Cell;
-use ansi_term::Colour;
-use ansi_term::Style;
+pub use ansi_term::Colour;
+pub use ansi_term::Style;
 use std::io;
 
 
+pub fn get_style_by_level(l: LogLevel) -> Style {
+    match l {
+        LogLevel::Error => Colour::Red.normal(),
+        LogLevel::Trace => Colour::White.normal(),
+        LogLevel::Debug => Colour::Green.normal(),
+        LogLevel::Warn => Colour::Purple.normal(),
+        _ => Style::default()
+    }
+}
+
 pub fn get_color_by_level(l: LogLevel, msg: &str) -> String {
-    let res = match l {
-        LogLevel::Error => Colour::Red.paint(msg),
-        LogLevel::Trace => Colour::White.paint(msg),
-        LogLevel::Debug => Colour::Green.paint(msg),
-        LogLevel::Warn => Colour::Purple.paint(msg),
-        _ => Style::default().paint(msg)
-    };
-    res.to_string()
+    get_style_by_level(l).paint(msg).to_string()
+}
+
+
+//日志级别到样式的映射, 默认与get_style_by_level一致
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Palette {
+    error: Style,
+    warn: Style,
+    info: Style,
+    debug: Style,
+    trace: Style,
+}
+
+impl Palette {
+    pub fn new() -> Palette {
+        Palette {
+            error: get_style_by_level(LogLevel::Error),
+            warn: get_style_by_level(LogLevel::Warn),
+            info: get_style_by_level(LogLevel::Info),
+            debug: get_style_by_level(LogLevel::Debug),
+            trace: get_style_by_level(LogLevel::Trace),
+        }
+    }
+
+    pub fn set(&mut self, l: LogLevel, style: Style) -> &mut Self {
+        match l {
+            LogLevel::Error => self.error = style,
+            LogLevel::Warn => self.warn = style,
+            LogLevel::Info => self.info = style,
+            LogLevel::Debug => self.debug = style,
+            LogLevel::Trace => self.trace = style,
+        }
+        self
+    }
+
+    #[inline]
+    pub fn style(&self, l: LogLevel) -> Style {
+        match l {
+            LogLevel::Error => self.error,
+            LogLevel::Warn => self.warn,
+            LogLevel::Info => self.info,
+            LogLevel::Debug => self.debug,
+            LogLevel::Trace => self.trace,
+        }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette::new()
+    }
 }
 
+//空格分隔的样式描述, 例如 `bold red`, `dimmed`, `208`(256色), `#ff8800`(真彩色), `white on_red`
+pub fn parse_style(spec: &str) -> Option<Style> {
+    let mut style = Style::default();
+    let mut empty = true;
+    for token in spec.split_whitespace() {
+        style = match token {
+            "bold" => style.bold(),
+            "dimmed" | "dim" => style.dimmed(),
+            "italic" => style.italic(),
+            "underline" => style.underline(),
+            "blink" => style.blink(),
+            "reverse" => style.reverse(),
+            "hidden" => style.hidden(),
+            "strikethrough" => style.strikethrough(),
+            _ if token.starts_with("on_") => match parse_colour(&token[3..]) {
+                Some(colour) => style.on(colour),
+                None => return None,
+            },
+            _ => match parse_colour(token) {
+                Some(colour) => style.fg(colour),
+                None => return None,
+            },
+        };
+        empty = false;
+    }
+    if empty { None } else { Some(style) }
+}
+
+fn parse_colour(name: &str) -> Option<Colour> {
+    match name {
+        "black" => Some(Colour::Black),
+        "red" => Some(Colour::Red),
+        "green" => Some(Colour::Green),
+        "yellow" => Some(Colour::Yellow),
+        "blue" => Some(Colour::Blue),
+        "purple" | "magenta" => Some(Colour::Purple),
+        "cyan" => Some(Colour::Cyan),
+        "white" => Some(Colour::White),
+        _ if name.starts_with('#') && name.len() == 7 && name.is_ascii() => {
+            match (u8::from_str_radix(&name[1..3], 16), u8::from_str_radix(&name[3..5], 16), u8::from_str_radix(&name[5..7], 16)) {
+                (Ok(r), Ok(g), Ok(b)) => Some(Colour::RGB(r, g, b)),
+                _ => None,
+            }
+        }
+        _ => name.parse::<u8>().ok().map(Colour::Fixed),
+    }
+}
@@ -10,22 +10,40 @@ extern crate time;
 extern crate crossbeam;
 extern crate chrono;
 extern crate ansi_term;
+extern crate libc;
+extern crate flate2;
+#[cfg(feature = "zstd")]
+extern crate zstd;
 
 use log::{LogLevel, LogLevelFilter, LogLocation, SetLoggerError, LogMetadata, LogRecord};
 use std::collections::HashMap;
 use std::mem;
+use std::fmt;
+use std::thread;
+use std::env;
+use std::ffi::CStr;
+use std::path::Path;
+use std::fs;
+use std::sync::atomic::{AtomicUsize, AtomicU64, Ordering, ATOMIC_USIZE_INIT};
 
 
 pub mod format;
 pub mod output;
 pub mod channel;
-mod level_color;
+pub mod level_color;
+pub mod sanitize;
+pub mod redact;
 
 use std::sync::{Arc};
-use time::{Timespec};
+use sanitize::SanitizeMode;
+use time::{Timespec, Duration};
 
 const DEFAULT_FORMAT_STRING: &'static str = "%{datetime:rfc3339}\t%{level}:\t%{modulePath}\t%{message}";
 
+static NEXT_THREAD_ID: AtomicUsize = ATOMIC_USIZE_INIT;
+
+thread_local!(static THREAD_ID: usize = NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed) + 1);
+
 type LogExactExecutors = HashMap<&'static str, HashMap<&'static str, LogExecute>>;
 type LogModuleExecutors = HashMap<&'static str, LogExecute>;
 type LogPrefixModuleExecutors = HashMap<&'static str, LogExecute>;
@@ -34,6 +52,9 @@ type LogTargetExecutors = HashMap<&'static str, LogExecute>;
 
 pub trait Channeled: Send + Sync {
     fn send(&self, strings: Arc<LogEntry>);
+
+    //等已经发送的记录写出, 并刷新各个输出的缓冲
+    fn flush(&self) {}
 }
 
 pub trait Parted {
@@ -43,7 +64,14 @@ pub trait Parted {
 
 
 pub trait Formatter: Send + Sync {
-    fn parse(&self, color: bool, record: &LogEntry) -> String;
+    //sanitize为输出端的默认设置, 由Output::sanitize提供
+    fn format(&self, color: bool, sanitize: SanitizeMode, record: &LogEntry, buf: &mut fmt::Write) -> fmt::Result;
+
+    fn parse(&self, color: bool, record: &LogEntry) -> String {
+        let mut res = String::with_capacity(100);
+        let _ = self.format(color, SanitizeMode::Off, record, &mut res);
+        res
+    }
 }
 
 
@@ -58,6 +86,12 @@ pub struct LogEntry {
     msg: String,
     level: LogLevel,
     time: Timespec,
+    thread_id: usize,
+    thread_name: Option<String>,
+    target: String,
+    process: Arc<ProcessInfo>,
+    seq: usize,
+    delta: Duration,
 }
 
 impl LogEntry {
@@ -72,6 +106,151 @@ impl LogEntry {
     pub fn level(&self) -> LogLevel {
         self.level
     }
+
+    //记录日志的线程,格式化发生在channel的worker线程,所以在这里保存
+    pub fn thread_id(&self) -> usize {
+        self.thread_id
+    }
+
+    pub fn thread_name(&self) -> Option<&str> {
+        self.thread_name.as_ref().map(|s| s.as_str())
+    }
+
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    pub fn process(&self) -> &ProcessInfo {
+        &self.process
+    }
+
+    //进程内单调递增的序号,出现断档说明有记录丢失
+    pub fn seq(&self) -> usize {
+        self.seq
+    }
+
+    pub fn time(&self) -> Timespec {
+        self.time
+    }
+
+    //距同一路由上一条记录的时间, 第一条记录为0
+    pub fn delta(&self) -> Duration {
+        self.delta
+    }
+
+    fn with_message(&self, msg: String) -> LogEntry {
+        LogEntry {
+            location: self.location,
+            msg: msg,
+            level: self.level,
+            time: self.time,
+            thread_id: self.thread_id,
+            thread_name: self.thread_name.clone(),
+            target: self.target.clone(),
+            process: self.process.clone(),
+            seq: self.seq,
+            delta: self.delta,
+        }
+    }
+}
+
+//在init_logger时采集一次的进程信息
+pub struct ProcessInfo {
+    pid: u32,
+    hostname: String,
+    app_name: String,
+    init_time: Timespec,
+    start_time: Timespec,
+}
+
+impl ProcessInfo {
+    pub fn capture(app_name: Option<&str>) -> ProcessInfo {
+        let app_name = match app_name {
+            Some(name) => name.to_string(),
+            None => env::args().next()
+                .and_then(|arg0| Path::new(&arg0).file_name().map(|n| n.to_string_lossy().into_owned()))
+                .unwrap_or_default(),
+        };
+
+        let now = time::get_time();
+        ProcessInfo {
+            pid: unsafe { libc::getpid() } as u32,
+            hostname: hostname(),
+            app_name: app_name,
+            init_time: now,
+            start_time: process_start_time().unwrap_or(now),
+        }
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    pub fn hostname(&self) -> &str {
+        &self.hostname
+    }
+
+    pub fn app_name(&self) -> &str {
+        &self.app_name
+    }
+
+    //logger初始化的时间
+    pub fn init_time(&self) -> Timespec {
+        self.init_time
+    }
+
+    //进程启动的时间,取不到时与init_time相同
+    pub fn start_time(&self) -> Timespec {
+        self.start_time
+    }
+}
+
+//由/proc/self/stat的starttime(开机后的时钟周期数)和/proc/stat的btime计算
+fn process_start_time() -> Option<Timespec> {
+    let stat = match fs::read_to_string("/proc/self/stat") {
+        Ok(stat) => stat,
+        Err(_) => return None,
+    };
+    //comm字段可能包含空格和括号,从最后一个')'之后开始数,starttime是第22个字段
+    let start_ticks = match stat.rfind(')')
+        .and_then(|i| stat[i + 1..].split_whitespace().nth(19))
+        .and_then(|ticks| ticks.parse::<i64>().ok()) {
+        Some(ticks) => ticks,
+        None => return None,
+    };
+
+    let boot_time = match fs::read_to_string("/proc/stat").ok().and_then(|stat| {
+        stat.lines()
+            .find(|line| line.starts_with("btime "))
+            .and_then(|line| line[6..].trim().parse::<i64>().ok())
+    }) {
+        Some(secs) => secs,
+        None => return None,
+    };
+
+    let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as i64;
+    if ticks_per_sec <= 0 {
+        return None;
+    }
+    Some(Timespec::new(boot_time + start_ticks / ticks_per_sec,
+                       ((start_ticks % ticks_per_sec) * 1_000_000_000 / ticks_per_sec) as i32))
+}
+
+fn hostname() -> String {
+    let mut buf = [0 as libc::c_char; 256];
+    let res = unsafe { libc::gethostname(buf.as_mut_ptr(), buf.len()) };
+    if res != 0 {
+        return String::new();
+    }
+    //名字被截断时不保证以\0结尾
+    buf[buf.len() - 1] = 0;
+    unsafe { CStr::from_ptr(buf.as_ptr()) }.to_string_lossy().into_owned()
+}
+
+//进程内从1开始递增的线程编号
+#[inline]
+fn current_thread_id() -> usize {
+    THREAD_ID.with(|id| *id)
 }
 
 
@@ -93,38 +272,52 @@ impl PartialEq for LogDirective {
 struct LogExecute {
     //控制过滤条件到指定的channel
     channels: HashMap<Option<i32>, Vec<Arc<Channeled>>>,
+    //本路由上一条发出的记录的时间(纳秒, 0表示还没有),用于%{delta}
+    last_time: AtomicU64,
 }
 
 impl LogExecute {
     #[inline]
-    fn control(&self, record: &LogRecord) {
+    fn control(&self, record: &LogRecord, process: &Arc<ProcessInfo>, seq: &AtomicUsize) {
         let level = record.level() as usize();
 
+        //只有通过级别过滤、真正发出去的记录才占用seq和更新delta
+        let mut targets = self.channels.iter().filter(|&(level_key, _)| {
+            match level_key {
+                &Some(l) => level <= l as usize,
+                &None => true,
+            }
+        }).flat_map(|(_, channels)| channels.iter()).peekable();
+        if targets.peek().is_none() {
+            return;
+        }
+
         let now = time::get_time();
+        let nanos = now.sec as u64 * 1_000_000_000 + now.nsec as u64;
+        let last = self.last_time.swap(nanos, Ordering::Relaxed);
+        //并发时上一条可能取时间更晚, 这时按0算
+        let delta = if last == 0 || last >= nanos {
+            Duration::zero()
+        } else {
+            Duration::nanoseconds((nanos - last) as i64)
+        };
 
         let entry = LogEntry {
             level: record.level(),
             msg: format!("{}", record.args()),
             location: record.location().clone(),
             time: now,
+            thread_id: current_thread_id(),
+            thread_name: thread::current().name().map(String::from),
+            target: record.target().to_string(),
+            process: process.clone(),
+            seq: seq.fetch_add(1, Ordering::Relaxed) + 1,
+            delta: delta,
         };
         let entry_arc = Arc::new(entry);
 
-        for (level_key, channels) in &self.channels {
-            match level_key {
-                &Some(l) => {
-                    if level > l as usize {
-                        return;
-                    };
-                }
-                &None => {
-                }
-            };
-
-
-            for channel in channels {
-                channel.clone().send(entry_arc.clone());
-            };
+        for channel in targets {
+            channel.send(entry_arc.clone());
         }
     }
 }
@@ -156,6 +349,7 @@ impl LogExecuteBuilder {
 
         LogExecute {
             channels: mem::replace(&mut clone, HashMap::new()),
+            last_time: AtomicU64::new(0),
         }
     }
 }
@@ -167,6 +361,8 @@ struct Logger {
     target_executors: LogTargetExecutors,
     module_executors: LogModuleExecutors,
     prefix_module_executors: LogPrefixModuleExecutors,
+    process: Arc<ProcessInfo>,
+    seq: AtomicUsize,
 }
 
 impl Logger {
@@ -186,7 +382,7 @@ impl Logger {
             self.find_default()
         }) {
             Some(execute) => {
-                execute.control(record);
+                execute.control(record, &self.process, &self.seq);
             }
             None => {}
         }
@@ -223,6 +419,25 @@ impl Logger {
     fn find_default(&self) -> Option<&LogExecute> {
         self.default.as_ref()
     }
+
+    //所有路由用到的channel, 同一个channel只出现一次
+    fn channels(&self) -> Vec<Arc<Channeled>> {
+        let executes = self.default.iter()
+            .chain(self.exact_executors.values().flat_map(|targets| targets.values()))
+            .chain(self.target_executors.values())
+            .chain(self.module_executors.values())
+            .chain(self.prefix_module_executors.values());
+
+        let mut res: Vec<Arc<Channeled>> = Vec::new();
+        for execute in executes {
+            for channel in execute.channels.values().flat_map(|channels| channels.iter()) {
+                if !res.iter().any(|c| Arc::ptr_eq(c, channel)) {
+                    res.push(channel.clone());
+                }
+            }
+        }
+        res
+    }
 }
 
 
@@ -245,6 +460,8 @@ pub struct LoggerBuilder {
     module_executors: LogModuleExecutors,
     prefix_module_executors: LogPrefixModuleExecutors,
     max_level: LogLevelFilter,
+    app_name: Option<String>,
+    reopen_on_sighup: bool,
 }
 
 
@@ -258,6 +475,8 @@ impl LoggerBuilder {
             module_executors: LogModuleExecutors::new(),
             prefix_module_executors: LogPrefixModuleExecutors::new(),
             max_level: LogLevelFilter::Trace,
+            app_name: None,
+            reopen_on_sighup: false,
         }
     }
 
@@ -301,6 +520,20 @@ impl LoggerBuilder {
         self
     }
 
+    //%{appName}使用的名字,默认为可执行文件名
+    #[inline]
+    pub fn app_name(&mut self, name: &str) -> &mut Self {
+        self.app_name = Some(name.to_string());
+        self
+    }
+
+
+    //收到SIGHUP时重新打开所有文件输出, 配合logrotate的create模式使用
+    #[inline]
+    pub fn reopen_on_sighup(&mut self, enable: bool) -> &mut Self {
+        self.reopen_on_sighup = enable;
+        self
+    }
 
     fn build(&mut self) -> Logger {
         Logger {
@@ -309,14 +542,55 @@ impl LoggerBuilder {
             target_executors: mem::replace(&mut self.target_executors, LogTargetExecutors::new()),
             module_executors: mem::replace(&mut self.module_executors, LogModuleExecutors::new()),
             prefix_module_executors: mem::replace(&mut self.prefix_module_executors, LogPrefixModuleExecutors::new()),
+            process: Arc::new(ProcessInfo::capture(self.app_name.as_ref().map(|s| s.as_str()))),
+            seq: AtomicUsize::new(0),
         }
     }
 
-    pub fn init_logger(&mut self) -> Result<(), SetLoggerError> {
+    pub fn init_logger(&mut self) -> Result<LoggerHandle, SetLoggerError> {
+        let mut channels = Vec::new();
         log::set_logger(|max_level| {
             max_level.set(self.max_level);
-            Box::new(self.build())
-        })
+            let logger = self.build();
+            channels = logger.channels();
+            Box::new(logger)
+        })?;
+
+        if self.reopen_on_sighup {
+            unsafe {
+                libc::signal(libc::SIGHUP, on_sighup as extern "C" fn(libc::c_int) as libc::sighandler_t);
+            }
+        }
+        Ok(LoggerHandle { channels: channels })
+    }
+}
+
+extern "C" fn on_sighup(_: libc::c_int) {
+    output::file::request_reopen();
+}
+
+//init_logger返回, 用来在运行时操作已经安装的logger
+pub struct LoggerHandle {
+    channels: Vec<Arc<Channeled>>,
+}
+
+impl LoggerHandle {
+    //所有文件输出在下一次写入前重新打开自己的路径
+    pub fn reopen(&self) {
+        output::file::request_reopen();
+    }
+
+    //等已经记录的日志写出并刷新所有输出的缓冲
+    pub fn flush(&self) {
+        for channel in &self.channels {
+            channel.flush();
+        }
+    }
+
+    //卸载logger, 之后的日志被丢弃, 再把已经记录的写出
+    pub fn shutdown(self) {
+        let _ = log::shutdown_logger();
+        self.flush();
     }
 }
 
@@ -332,7 +606,7 @@ fn format_parse() {
 
     let file = output::file::File::new("./a/a").expect("can't open file");
 
-    let o1 = Arc::new(OutputLock::new(io::stdout(), true));
+    let o1 = Arc::new(OutputLock::with_color_mode(io::stdout(), ColorMode::Auto));
 
     let o2 = Arc::new(OutputLock::new(file, false));
 
@@ -0,0 +1,160 @@
+use std::io::{Result, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use log::LogLevel;
+use super::file::File;
+use super::{Output, ErrorCounter};
+use LogEntry;
+
+struct Buffer {
+    file: File,
+    buf: Vec<u8>,
+}
+
+impl Buffer {
+    fn flush(&mut self) -> Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        //写失败也清空, 磁盘满时不让缓冲无限增长
+        let result = self.file.write_all(&self.buf);
+        self.buf.clear();
+        result?;
+        self.file.flush()
+    }
+}
+
+//带缓冲的文件输出, 满足任一条件时写入文件:
+//缓冲超过max_bytes, 距离上次写入超过interval, 记录级别不低于flush_level, 调用flush(例如LoggerHandle::shutdown)
+pub struct BufferedFile {
+    buffer: Arc<Mutex<Buffer>>,
+    max_bytes: usize,
+    flush_level: Option<LogLevel>,
+    errors: Arc<ErrorCounter>,
+}
+
+impl BufferedFile {
+    pub fn new(file: File) -> BufferedFile {
+        BufferedFile {
+            buffer: Arc::new(Mutex::new(Buffer {
+                file: file,
+                buf: Vec::with_capacity(8192),
+            })),
+            max_bytes: 8192,
+            flush_level: Some(LogLevel::Error),
+            errors: Arc::new(ErrorCounter::new()),
+        }
+    }
+
+    #[inline]
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    //None表示不按级别刷新
+    #[inline]
+    pub fn with_flush_level(mut self, level: Option<LogLevel>) -> Self {
+        self.flush_level = level;
+        self
+    }
+
+    //启动后台线程定时刷新, BufferedFile释放后线程退出
+    pub fn with_interval(self, interval: Duration) -> Self {
+        let buffer = Arc::downgrade(&self.buffer);
+        let errors = self.errors.clone();
+        thread::spawn(move || {
+            loop {
+                thread::sleep(interval);
+                match buffer.upgrade() {
+                    Some(buffer) => {
+                        if let Err(ref err) = buffer.lock().unwrap().flush() {
+                            errors.record(err);
+                        }
+                    }
+                    None => break,
+                }
+            }
+        });
+        self
+    }
+
+    fn write(&self, string: &str, force: bool) -> Result<()> {
+        let mut buffer = self.buffer.lock().unwrap();
+        buffer.buf.extend_from_slice(string.as_bytes());
+        if force || buffer.buf.len() >= self.max_bytes {
+            return buffer.flush();
+        }
+        Ok(())
+    }
+}
+
+impl Output for BufferedFile {
+    fn push(&self, string: &str) -> Result<()> {
+        self.write(string, false)
+    }
+
+    fn push_record(&self, record: &LogEntry, string: &str) -> Result<()> {
+        let force = self.flush_level.map_or(false, |level| record.level() <= level);
+        self.write(string, force)
+    }
+
+    fn has_color(&self) -> bool {
+        false
+    }
+
+    fn errors(&self) -> &ErrorCounter {
+        &self.errors
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.buffer.lock().unwrap().flush()
+    }
+}
+
+impl Drop for BufferedFile {
+    fn drop(&mut self) {
+        let result = Output::flush(self);
+        super::report(self, result);
+    }
+}
+
+#[test]
+fn buffered_flush_policies() {
+    use std::env;
+    use std::fs;
+    use std::process;
+    use format;
+
+    let dir = env::temp_dir().join(format!("intro-buffered-{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let path = dir.join("app.log");
+    let read = || fs::read_to_string(&path).unwrap();
+    let entry = |level: LogLevel| format::test_entry(level, "intro", "buffered.rs", 1, "");
+
+    let output = BufferedFile::new(File::new(&path).unwrap()).with_max_bytes(12);
+    output.push_record(&entry(LogLevel::Info), "info 1\n").unwrap();
+    assert_eq!(read(), "");
+    output.push_record(&entry(LogLevel::Info), "info 2\n").unwrap();
+    assert_eq!(read(), "info 1\ninfo 2\n");
+    output.push_record(&entry(LogLevel::Debug), "debug\n").unwrap();
+    output.push_record(&entry(LogLevel::Error), "error\n").unwrap();
+    assert_eq!(read(), "info 1\ninfo 2\ndebug\nerror\n");
+    output.push("tail\n").unwrap();
+    assert_eq!(read(), "info 1\ninfo 2\ndebug\nerror\n");
+    output.flush().unwrap();
+    assert_eq!(read(), "info 1\ninfo 2\ndebug\nerror\ntail\n");
+
+    output.push("dropped\n").unwrap();
+    drop(output);
+    assert!(read().ends_with("tail\ndropped\n"));
+
+    let timed = BufferedFile::new(File::new(dir.join("timed.log")).unwrap())
+        .with_interval(Duration::from_millis(10));
+    timed.push("later\n").unwrap();
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(fs::read_to_string(dir.join("timed.log")).unwrap(), "later\n");
+
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,206 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::fs::File as FsFile;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Condvar};
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use flate2;
+use flate2::write::GzEncoder;
+#[cfg(feature = "zstd")]
+use zstd;
+use super::ErrorCounter;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Compression {
+    Gzip,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl Compression {
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            Compression::Gzip => "gz",
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => "zst",
+        }
+    }
+
+    fn encode(&self, source: &mut FsFile, dest: &mut FsFile) -> io::Result<()> {
+        match *self {
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(dest, flate2::Compression::Default);
+                io::copy(source, &mut encoder)?;
+                encoder.finish().map(|_| ())
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => zstd::stream::copy_encode(source, dest, 0),
+        }
+    }
+}
+
+//在后台线程压缩滚动出来的文件, 不阻塞channel的worker
+//先写`app.log.1.gz.tmp`, 完成后再改名为`app.log.1.gz`并删除原文件
+#[derive(Clone)]
+pub struct Compressor {
+    compression: Compression,
+    sender: Arc<Mutex<Sender<PathBuf>>>,
+    //已提交还没有压缩完的文件
+    pending: Arc<(Mutex<Vec<PathBuf>>, Condvar)>,
+    errors: Arc<ErrorCounter>,
+}
+
+impl Compressor {
+    pub fn new(compression: Compression) -> Compressor {
+        let (sender, receiver) = channel::<PathBuf>();
+        let pending = Arc::new((Mutex::new(Vec::new()), Condvar::new()));
+
+        let errors = Arc::new(ErrorCounter::new());
+
+        let queued = pending.clone();
+        let counter = errors.clone();
+        thread::spawn(move || {
+            for path in receiver.iter() {
+                //压缩失败时保留原文件
+                if let Err(ref err) = compress(compression, &path) {
+                    counter.record(err);
+                }
+                let &(ref lock, ref cvar) = &*queued;
+                let mut pending = lock.lock().unwrap();
+                if let Some(index) = pending.iter().position(|p| *p == path) {
+                    pending.remove(index);
+                }
+                cvar.notify_all();
+            }
+        });
+
+        Compressor {
+            compression: compression,
+            sender: Arc::new(Mutex::new(sender)),
+            pending: pending,
+            errors: errors,
+        }
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    pub fn errors(&self) -> &ErrorCounter {
+        &self.errors
+    }
+
+    pub fn compressed_path(&self, path: &Path) -> PathBuf {
+        append_extension(path, self.compression.extension())
+    }
+
+    pub fn submit(&self, path: PathBuf) {
+        let &(ref lock, _) = &*self.pending;
+        let mut pending = lock.lock().unwrap();
+        if self.sender.lock().unwrap().send(path.clone()).is_ok() {
+            pending.push(path);
+        }
+    }
+
+    //不阻塞, 滚动的文件用它判断现在能不能改名或者重新打开
+    pub fn is_idle(&self) -> bool {
+        let &(ref lock, _) = &*self.pending;
+        lock.lock().unwrap().is_empty()
+    }
+
+    pub fn is_pending(&self, path: &Path) -> bool {
+        let &(ref lock, _) = &*self.pending;
+        lock.lock().unwrap().iter().any(|p| p == path)
+    }
+
+    //等待已提交的文件全部压缩完, 例如退出之前
+    pub fn wait(&self) {
+        let &(ref lock, ref cvar) = &*self.pending;
+        let mut pending = lock.lock().unwrap();
+        while !pending.is_empty() {
+            pending = cvar.wait(pending).unwrap();
+        }
+    }
+}
+
+pub fn append_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(OsStr::new("."));
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+fn compress(compression: Compression, path: &Path) -> io::Result<()> {
+    let target = append_extension(path, compression.extension());
+    let tmp = append_extension(&target, "tmp");
+
+    let result = (|| {
+        let mut dest = FsFile::create(&tmp)?;
+        //迟到的记录重新写了已经压缩过的文件时, 在原有内容后追加一个新的gzip/zstd帧
+        if let Ok(mut existing) = FsFile::open(&target) {
+            io::copy(&mut existing, &mut dest)?;
+        }
+        let mut source = FsFile::open(path)?;
+        compression.encode(&mut source, &mut dest)?;
+        dest.sync_all()
+    })();
+
+    match result {
+        Ok(()) => {
+            fs::rename(&tmp, &target)?;
+            fs::remove_file(path)
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            Err(e)
+        }
+    }
+}
+
+#[test]
+fn compress_rotated_files() {
+    use std::env;
+    use std::io::Read;
+    use std::process;
+    use flate2::read::MultiGzDecoder;
+    use output::{Output, OutputLock};
+    use output::rolling::SizeRollingFile;
+
+    let dir = env::temp_dir().join(format!("intro-compress-{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let path = dir.join("app.log");
+
+    let compressor = Compressor::new(Compression::Gzip);
+    let rolling = SizeRollingFile::new(&path, 20, 2).unwrap().with_compressor(compressor.clone());
+    let output = OutputLock::new(rolling, false);
+    for i in 1..8 {
+        output.push(&format!("line {}\n", i)).unwrap();
+        //上一个备份还在压缩时不滚动
+        compressor.wait();
+    }
+
+    let read = |name: &str| {
+        let mut text = String::new();
+        MultiGzDecoder::new(FsFile::open(dir.join(name)).unwrap()).unwrap().read_to_string(&mut text).unwrap();
+        text
+    };
+    assert_eq!(read("app.log.2.gz"), "line 1\nline 2\nline 3\n");
+    assert_eq!(read("app.log.1.gz"), "line 4\nline 5\nline 6\n");
+    assert!(!dir.join("app.log.1").exists());
+    assert!(!dir.join("app.log.1.gz.tmp").exists());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "line 7\n");
+
+    //同一个文件再次压缩时追加
+    fs::write(dir.join("app.log.1"), "line 8\n").unwrap();
+    compressor.submit(dir.join("app.log.1"));
+    compressor.wait();
+    assert_eq!(read("app.log.1.gz"), "line 4\nline 5\nline 6\nline 8\n");
+
+    compressor.submit(dir.join("missing.log"));
+    compressor.wait();
+    assert_eq!(compressor.errors().count(), 1);
+
+    let _ = fs::remove_dir_all(&dir);
+}
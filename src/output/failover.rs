@@ -0,0 +1,187 @@
+use std::io::Result;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use chrono::Local;
+use super::{Output, ErrorCounter};
+use LogEntry;
+use sanitize::SanitizeMode;
+
+struct State {
+    //当前使用的输出, 0为主输出
+    active: usize,
+    //上次尝试主输出的时间
+    retried: Instant,
+}
+
+//主输出写失败时依次切换到备用输出, 每隔retry_interval再尝试主输出,
+//切换时在新的输出上先写一条标记记录; 全部失败时返回最后一个错误
+pub struct Failover {
+    outputs: Vec<Arc<Output>>,
+    retry_interval: Duration,
+    state: Mutex<State>,
+    errors: ErrorCounter,
+}
+
+impl Failover {
+    pub fn new(primary: Arc<Output>) -> Failover {
+        Failover {
+            outputs: vec![primary],
+            retry_interval: Duration::from_secs(30),
+            state: Mutex::new(State {
+                active: 0,
+                retried: Instant::now(),
+            }),
+            errors: ErrorCounter::new(),
+        }
+    }
+
+    #[inline]
+    pub fn with_fallback(mut self, output: Arc<Output>) -> Self {
+        self.outputs.push(output);
+        self
+    }
+
+    #[inline]
+    pub fn with_retry_interval(mut self, interval: Duration) -> Self {
+        self.retry_interval = interval;
+        self
+    }
+
+    pub fn active(&self) -> usize {
+        self.state.lock().unwrap().active
+    }
+
+    fn write(&self, record: Option<&LogEntry>, string: &str) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+
+        //先写标记记录试探主输出, 成功就切回去
+        if state.active > 0 && state.retried.elapsed() >= self.retry_interval {
+            state.retried = Instant::now();
+            let marker = format!("{} intro: output 0 recovered, switching back from output {}\n",
+                                 Local::now().to_rfc3339(), state.active);
+            match self.outputs[0].push(&marker) {
+                Ok(()) => state.active = 0,
+                Err(ref err) => self.outputs[0].errors().record(err),
+            }
+        }
+
+        let mut index = state.active;
+        loop {
+            let output = &self.outputs[index];
+            let mut result = Ok(());
+            if index != state.active {
+                let marker = format!("{} intro: output {} failed, switching to output {}\n",
+                                     Local::now().to_rfc3339(), state.active, index);
+                result = output.push(&marker);
+            }
+            result = result.and_then(|_| match record {
+                Some(record) => output.push_record(record, string),
+                None => output.push(string),
+            });
+
+            match result {
+                Ok(()) => {
+                    if index != state.active {
+                        state.active = index;
+                        state.retried = Instant::now();
+                    }
+                    return Ok(());
+                }
+                Err(err) => {
+                    if index + 1 == self.outputs.len() {
+                        return Err(err);
+                    }
+                    output.errors().record(&err);
+                    index += 1;
+                }
+            }
+        }
+    }
+}
+
+impl Output for Failover {
+    fn push(&self, string: &str) -> Result<()> {
+        self.write(None, string)
+    }
+
+    fn push_record(&self, record: &LogEntry, string: &str) -> Result<()> {
+        self.write(Some(record), string)
+    }
+
+    //按主输出的设置格式化
+    fn has_color(&self) -> bool {
+        self.outputs[0].has_color()
+    }
+
+    fn sanitize(&self) -> SanitizeMode {
+        self.outputs[0].sanitize()
+    }
+
+    fn errors(&self) -> &ErrorCounter {
+        &self.errors
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.outputs.iter().fold(Ok(()), |res, output| res.and(output.flush()))
+    }
+
+    fn batch_end(&self) -> Result<()> {
+        let active = self.active();
+        self.outputs[active].batch_end()
+    }
+}
+
+#[test]
+fn failover_switches_and_recovers() {
+    use std::io::{self, Error, ErrorKind, Write};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use output::{OutputLock, ReadLock};
+
+    struct Flaky {
+        fail: Arc<AtomicBool>,
+        lines: Vec<u8>,
+    }
+    impl Write for Flaky {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.fail.load(Ordering::SeqCst) {
+                return Err(Error::new(ErrorKind::Other, "No space left on device"));
+            }
+            self.lines.write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let fail = Arc::new(AtomicBool::new(false));
+    let primary = Arc::new(OutputLock::new(Flaky { fail: fail.clone(), lines: Vec::new() }, false));
+    let fallback = Arc::new(OutputLock::new(Vec::new(), false));
+    let failover = Failover::new(primary.clone())
+        .with_fallback(fallback.clone())
+        .with_retry_interval(Duration::from_secs(0));
+
+    failover.push("a\n").unwrap();
+    fail.store(true, Ordering::SeqCst);
+    failover.push("b\n").unwrap();
+    assert_eq!(failover.active(), 1);
+    failover.push("c\n").unwrap();
+    fail.store(false, Ordering::SeqCst);
+    failover.push("d\n").unwrap();
+    assert_eq!(failover.active(), 0);
+
+    let primary_lines = String::from_utf8(primary.arc_lock().read().unwrap().lines.clone()).unwrap();
+    let fallback_lines = String::from_utf8(fallback.arc_lock().read().unwrap().clone()).unwrap();
+    let primary_lines: Vec<&str> = primary_lines.lines().collect();
+    let fallback_lines: Vec<&str> = fallback_lines.lines().collect();
+    assert_eq!(primary_lines.len(), 3);
+    assert_eq!((primary_lines[0], primary_lines[2]), ("a", "d"));
+    assert!(primary_lines[1].ends_with("intro: output 0 recovered, switching back from output 1"));
+    assert_eq!(fallback_lines.len(), 3);
+    assert!(fallback_lines[0].ends_with("intro: output 0 failed, switching to output 1"));
+    assert_eq!(&fallback_lines[1..], &["b", "c"]);
+    assert_eq!(primary.errors().count(), 2);
+
+    fail.store(true, Ordering::SeqCst);
+    let single = Failover::new(primary.clone());
+    assert!(single.push("e\n").is_err());
+}
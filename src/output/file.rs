@@ -6,39 +6,136 @@ use std::fs::DirBuilder;
 use std::io::Write;
 use std::io::Error;
 use std::io::ErrorKind;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::os::unix::io::AsRawFd;
 use std::os::unix::io::RawFd;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt, DirBuilderExt, PermissionsExt};
+use std::fs;
+use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
 
+//每次请求重新打开时加一, File在下一次写入时发现变化就重新打开自己的路径
+static REOPEN_GENERATION: AtomicUsize = ATOMIC_USIZE_INIT;
+
+//让所有File在下一次写入前重新打开路径, 只修改一个原子变量, 可以在信号处理函数里调用
+pub fn request_reopen() {
+    REOPEN_GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Durability {
+    //只写入页缓存, 由系统决定何时落盘
+    None,
+    //每批记录写完后fdatasync, 由channel的worker在队列为空或一批达到上限时通过flush触发
+    Batch,
+    //每条记录写完换行后fsync
+    Record,
+}
+
+//文件和目录的权限, 新建时使用, 创建之后再设置一次, 不受umask影响; 已经存在的文件和目录不修改
+#[derive(Clone, Debug)]
+pub struct FileBuilder {
+    path: PathBuf,
+    mode: Option<u32>,
+    dir_mode: Option<u32>,
+}
+
+impl FileBuilder {
+    pub fn new<P: AsRef<Path>>(path: P) -> FileBuilder {
+        FileBuilder {
+            path: path.as_ref().to_path_buf(),
+            mode: None,
+            dir_mode: None,
+        }
+    }
+
+    //例如0o640
+    pub fn mode(&mut self, mode: u32) -> &mut Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    //创建上级目录时使用, 例如0o750
+    pub fn dir_mode(&mut self, mode: u32) -> &mut Self {
+        self.dir_mode = Some(mode);
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn build(&self) -> Result<File> {
+        let generation = REOPEN_GENERATION.load(Ordering::SeqCst);
+        let file = open(&self.path, self.mode, self.dir_mode)?;
+
+        Ok(File {
+            file: file,
+            durability: Durability::None,
+            path: self.path.clone(),
+            mode: self.mode,
+            dir_mode: self.dir_mode,
+            generation: generation,
+            check_interval: Some(Duration::from_secs(1)),
+            checked: Instant::now(),
+            reopen_error: None,
+        })
+    }
+}
+
+//以O_APPEND打开, 每次write都追加到文件末尾;
+//OutputLock把一条记录连同换行放在一次write里, 多个进程同时写同一个文件时不会交错出半行
 pub struct File {
-    file: FsFile
+    file: FsFile,
+    durability: Durability,
+    path: PathBuf,
+    mode: Option<u32>,
+    dir_mode: Option<u32>,
+    generation: usize,
+    check_interval: Option<Duration>,
+    checked: Instant,
+    //重新打开失败的错误, 由下一次flush返回
+    reopen_error: Option<Error>,
 }
 
 impl File {
     pub fn new<P: AsRef<Path>>(path: P) -> Result<File> {
-        let path_ref = &path.as_ref();
-        let parent = &path.as_ref()
-            .parent()
-            .ok_or(Error::new(ErrorKind::InvalidInput,
-                              format!("file {:?} is invaild input ", &path_ref)))?;
+        FileBuilder::new(path).build()
+    }
 
-        DirBuilder::new().recursive(true).create(parent)?;
+    #[inline]
+    pub fn with_durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
 
-        let _ = &path_ref
-            .file_name()
-            .ok_or(Error::new(ErrorKind::InvalidInput,
-                              format!("file {:?} is invalid input,not have file name", &path_ref)));
+    //每隔interval检查一次路径上的文件是否还是打开的那个, 被删除或移走时重新打开, None表示不检查
+    #[inline]
+    pub fn with_check_interval(mut self, interval: Option<Duration>) -> Self {
+        self.check_interval = interval;
+        self
+    }
 
+    //打开的fd和路径上的文件inode/设备号不同, 或者路径已经不存在
+    pub fn is_detached(&self) -> bool {
+        match (self.file.metadata(), fs::metadata(&self.path)) {
+            (Ok(opened), Ok(current)) => opened.dev() != current.dev() || opened.ino() != current.ino(),
+            (Ok(_), Err(ref e)) if e.kind() == ErrorKind::NotFound => true,
+            _ => false,
+        }
+    }
 
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .write(true)
-            .open(&path)?;
+    //logrotate改名之后按原路径重新打开, 失败时继续写原来的文件
+    //失败时不更新generation, 下一次写入再重试
+    pub fn reopen(&mut self) -> Result<()> {
+        let generation = REOPEN_GENERATION.load(Ordering::SeqCst);
+        self.file = open(&self.path, self.mode, self.dir_mode)?;
+        self.generation = generation;
+        Ok(())
+    }
 
-        Ok(File {
-            file: file
-        })
+    pub fn path(&self) -> &Path {
+        &self.path
     }
 
     pub fn metadata(&self) -> Result<Metadata> {
@@ -46,6 +143,55 @@ impl File {
     }
 }
 
+fn open<P: AsRef<Path>>(path: P, mode: Option<u32>, dir_mode: Option<u32>) -> Result<FsFile> {
+    let path_ref = &path.as_ref();
+    let parent = &path.as_ref()
+        .parent()
+        .ok_or(Error::new(ErrorKind::InvalidInput,
+                          format!("file {:?} is invaild input ", &path_ref)))?;
+
+    //不存在的上级目录, 从最外层开始
+    let mut created: Vec<&Path> = parent.ancestors()
+        .take_while(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+        .collect();
+    created.reverse();
+
+    let mut dir_builder = DirBuilder::new();
+    if let Some(dir_mode) = dir_mode {
+        dir_builder.mode(dir_mode);
+    }
+    dir_builder.recursive(true).create(parent)?;
+
+    //DirBuilder的mode同样会被umask去掉一部分
+    if let Some(dir_mode) = dir_mode {
+        for dir in created {
+            fs::set_permissions(dir, fs::Permissions::from_mode(dir_mode))?;
+        }
+    }
+
+    let _ = &path_ref
+        .file_name()
+        .ok_or(Error::new(ErrorKind::InvalidInput,
+                          format!("file {:?} is invalid input,not have file name", &path_ref)));
+
+    let exists = path_ref.exists();
+    let mut options = OpenOptions::new();
+    options.create(true)
+        .append(true)
+        .write(true);
+    if let Some(mode) = mode {
+        options.mode(mode);
+    }
+    let file = options.open(&path)?;
+
+    //open的mode会被umask去掉一部分, 新建的文件再设置一次
+    if let (false, Some(mode)) = (exists, mode) {
+        file.set_permissions(fs::Permissions::from_mode(mode))?;
+    }
+
+    Ok(file)
+}
+
 impl AsRawFd for File {
     fn as_raw_fd(&self) -> RawFd {
         self.file.as_raw_fd()
@@ -55,12 +201,162 @@ impl AsRawFd for File {
 
 impl Write for File {
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
-        self.file.write(buf)
+        //重新打开失败时继续写原来的文件, 错误留给flush返回
+        if self.generation != REOPEN_GENERATION.load(Ordering::Relaxed) {
+            if let Err(e) = self.reopen() {
+                self.reopen_error = Some(e);
+            }
+        } else if let Some(interval) = self.check_interval {
+            if self.checked.elapsed() >= interval {
+                self.checked = Instant::now();
+                if self.is_detached() {
+                    if let Err(e) = self.reopen() {
+                        self.reopen_error = Some(e);
+                    }
+                }
+            }
+        }
+        let n = self.file.write(buf)?;
+        if self.durability == Durability::Record && n == buf.len() && buf.ends_with(b"\n") {
+            self.file.sync_all()?;
+        }
+        Ok(n)
     }
 
     fn flush(&mut self) -> Result<()> {
-        self.file.flush()
+        if let Some(e) = self.reopen_error.take() {
+            return Err(e);
+        }
+        self.file.flush()?;
+        if self.durability == Durability::Batch {
+            self.file.sync_data()?;
+        }
+        Ok(())
     }
 }
 
+#[test]
+fn reopen_after_rename() {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    let dir = env::temp_dir().join(format!("intro-reopen-{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let path = dir.join("app.log");
+    let moved = dir.join("app.log.1");
+
+    let mut file = File::new(&path).unwrap().with_check_interval(None);
+    file.write_all(b"before\n").unwrap();
+    fs::rename(&path, &moved).unwrap();
+    file.write_all(b"still old\n").unwrap();
+
+    request_reopen();
+    file.write_all(b"after\n").unwrap();
+    assert_eq!(fs::read_to_string(&moved).unwrap(), "before\nstill old\n");
+    assert_eq!(fs::read_to_string(&path).unwrap(), "after\n");
+
+    fs::rename(&path, &moved).unwrap();
+    file.reopen().unwrap();
+    file.write_all(b"again\n").unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "again\n");
+
+    //路径被目录占住时重新打开失败, 之后的写入继续重试
+    fs::rename(&path, &moved).unwrap();
+    fs::create_dir(&path).unwrap();
+    request_reopen();
+    file.write_all(b"blocked\n").unwrap();
+    assert!(file.flush().is_err());
+    assert!(file.flush().is_ok());
+    assert_eq!(fs::read_to_string(&moved).unwrap(), "again\nblocked\n");
+    fs::remove_dir(&path).unwrap();
+    file.write_all(b"retried\n").unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "retried\n");
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn recover_deleted_file() {
+    use std::env;
+    use std::process;
+
+    let dir = env::temp_dir().join(format!("intro-detached-{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let path = dir.join("app.log");
+
+    let mut file = File::new(&path).unwrap().with_check_interval(Some(Duration::from_secs(0)));
+    file.write_all(b"first\n").unwrap();
+    assert!(!file.is_detached());
+
+    fs::remove_file(&path).unwrap();
+    assert!(file.is_detached());
+    file.write_all(b"second\n").unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "second\n");
+
+    fs::rename(&path, dir.join("moved.log")).unwrap();
+    file.write_all(b"third\n").unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "third\n");
+    assert_eq!(fs::read_to_string(dir.join("moved.log")).unwrap(), "second\n");
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn file_and_dir_modes() {
+    use std::env;
+    use std::process;
+
+    let dir = env::temp_dir().join(format!("intro-modes-{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let path = dir.join("logs").join("app.log");
+
+    //组可写的权限在常见的umask 022下也要保留
+    let mut file = FileBuilder::new(&path).mode(0o660).dir_mode(0o770).build().unwrap();
+    let mode = |p: &Path| fs::metadata(p).unwrap().permissions().mode() & 0o7777;
+    assert_eq!(mode(&path), 0o660);
+    assert_eq!(mode(path.parent().unwrap()), 0o770);
+    assert_eq!(mode(&dir), 0o770);
+
+    fs::remove_file(&path).unwrap();
+    file.reopen().unwrap();
+    assert_eq!(mode(&path), 0o660);
+
+    let _ = fs::remove_dir_all(&dir);
+}
 
+#[test]
+fn append_does_not_interleave() {
+    use std::env;
+    use std::process;
+    use std::sync::Arc;
+    use std::thread;
+    use output::{Output, OutputLock};
+
+    let dir = env::temp_dir().join(format!("intro-append-{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let path = dir.join("shared.log");
+
+    //每个线程各自打开文件, 和多个进程一样没有共享的偏移量和锁
+    let writers: Vec<_> = (0..4).map(|id| {
+        let output = Arc::new(OutputLock::new(File::new(&path).unwrap(), false));
+        thread::spawn(move || {
+            let line = format!("writer {} {}\n", id, "x".repeat(200));
+            for _ in 0..500 {
+                output.push(&line).unwrap();
+            }
+        })
+    }).collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    let content = fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = content.lines().collect();
+    assert_eq!(lines.len(), 2000);
+    for line in lines {
+        assert!(line.starts_with("writer ") && line.ends_with(&"x".repeat(200)) && line.len() == 209, "{}", line);
+    }
+
+    let _ = fs::remove_dir_all(&dir);
+}
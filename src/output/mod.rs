@@ -1,13 +1,151 @@
 use std::marker::Send;
+use std::any::Any;
+use std::io;
 use std::io::Write;
-use std::sync::{Arc};
+use std::sync::{Arc, Mutex};
 use std::sync::{RwLock, LockResult, RwLockReadGuard};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::{Duration, Instant};
+use std::env;
+use std::ffi::OsString;
+use libc;
+use LogEntry;
+use sanitize::SanitizeMode;
 
 pub mod file;
+pub mod rolling;
+pub mod compress;
+pub mod buffered;
+pub mod failover;
 
+//写输出失败时调用, 参数为错误和该输出累计的错误数
+pub type ErrorHandler = Fn(&io::Error, usize) + Send + Sync;
+
+lazy_static! {
+    static ref ERROR_HANDLER: RwLock<Option<Arc<ErrorHandler>>> = RwLock::new(None);
+}
+
+//注册全局的错误回调, 每次写失败都会调用, 不受stderr报告的频率限制
+pub fn set_error_handler(handler: Arc<ErrorHandler>) {
+    *ERROR_HANDLER.write().unwrap() = Some(handler);
+}
+
+//去掉set_error_handler注册的回调
+pub fn clear_error_handler() {
+    *ERROR_HANDLER.write().unwrap() = None;
+}
+
+//两次stderr报告之间的最短间隔
+const REPORT_INTERVAL: u64 = 10;
+
+//一个输出的写入错误数, 第一次出错时和之后每隔REPORT_INTERVAL秒向stderr报告一次
+pub struct ErrorCounter {
+    count: AtomicUsize,
+    //上次报告的时间和当时的错误数
+    reported: Mutex<Option<(Instant, usize)>>,
+}
+
+impl ErrorCounter {
+    pub fn new() -> ErrorCounter {
+        ErrorCounter {
+            count: AtomicUsize::new(0),
+            reported: Mutex::new(None),
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    pub fn record(&self, err: &io::Error) {
+        let count = self.count.fetch_add(1, Ordering::Relaxed) + 1;
+
+        let handler = ERROR_HANDLER.read().unwrap().clone();
+        if let Some(handler) = handler {
+            handler(err, count);
+        }
+
+        let mut reported = self.reported.lock().unwrap();
+        let now = Instant::now();
+        let last = match *reported {
+            Some((at, _)) if now.duration_since(at) < Duration::from_secs(REPORT_INTERVAL) => return,
+            Some((_, last)) => last,
+            None => 0,
+        };
+        let _ = writeln!(io::stderr(), "intro: failed to write log output: {} ({} errors, {} since last report)",
+                         err, count, count - last);
+        *reported = Some((now, count));
+    }
+}
+
+//记录输出返回的错误, 由channel在push/flush之后调用
+pub fn report(output: &Output, result: io::Result<()>) {
+    if let Err(ref err) = result {
+        output.errors().record(err);
+    }
+}
+
+//string是一条完整的记录, 包括结尾的换行
 pub trait Output: Sync + Send + 'static {
-    fn push(&self, string: &str);
+    fn push(&self, string: &str) -> io::Result<()>;
     fn has_color(&self) -> bool;
+    fn errors(&self) -> &ErrorCounter;
+
+    //worker线程调用, 需要按记录内容(例如时间)选择目标的输出可以重写
+    fn push_record(&self, _record: &LogEntry, string: &str) -> io::Result<()> {
+        self.push(string)
+    }
+
+    //把缓冲中的内容写出, 关闭logger时调用
+    fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    //worker写完一批记录(队列为空, 或者达到一批的记录数或时间上限)时调用, 按Durability::Batch同步到磁盘
+    fn batch_end(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    //如何处理消息中的控制字符和ANSI序列, 默认按文件处理; 写终端的输出应返回SanitizeMode::Ansi
+    fn sanitize(&self) -> SanitizeMode {
+        SanitizeMode::Full
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ColorMode {
+    //CLICOLOR_FORCE非0时着色, 否则NO_COLOR非空时不着色, 都没有设置时看fd是否为终端
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorMode {
+    pub fn enabled(&self, fd: RawFd) -> bool {
+        self.enabled_with(fd, |name| env::var_os(name))
+    }
+
+    //var按名字取环境变量, 测试时可以不改进程的环境
+    pub fn enabled_with<F>(&self, fd: RawFd, var: F) -> bool
+        where F: Fn(&str) -> Option<OsString>
+    {
+        match *self {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            ColorMode::Auto => {
+                match var("CLICOLOR_FORCE") {
+                    Some(ref force) if !force.is_empty() && force != "0" => return true,
+                    _ => {}
+                }
+                match var("NO_COLOR") {
+                    Some(ref no_color) if !no_color.is_empty() => return false,
+                    _ => {}
+                }
+                unsafe { libc::isatty(fd) == 1 }
+            }
+        }
+    }
 }
 
 pub trait ReadLock<T>: Sync + Send
@@ -19,17 +157,63 @@ pub trait ReadLock<T>: Sync + Send
 
 pub struct OutputLock<T: Write> {
     lock: Arc<RwLock<T>>,
-    color: bool
+    color: bool,
+    sanitize: SanitizeMode,
+    errors: ErrorCounter,
+}
+
+//标准输出和标准错误是终端时只去掉ANSI序列, 保留消息中的换行; 重定向到文件时和其它输出一样完整转义
+fn default_sanitize(dir: &Any) -> SanitizeMode {
+    let fd = if dir.is::<io::Stdout>() {
+        libc::STDOUT_FILENO
+    } else if dir.is::<io::Stderr>() {
+        libc::STDERR_FILENO
+    } else {
+        return SanitizeMode::Full;
+    };
+    if unsafe { libc::isatty(fd) == 1 } {
+        SanitizeMode::Ansi
+    } else {
+        SanitizeMode::Full
+    }
 }
 
 impl<T> OutputLock<T>
     where T: Write + Send + Sync + 'static
 {
+    //默认转义消息中的控制字符, 文件等输出连换行一起转义成`\n`;
+    //终端上的io::stdout()/io::stderr()保留换行, 不需要时用with_sanitize(SanitizeMode::Off)
     #[inline]
     pub fn new(dir: T, color: bool) -> Self {
+        let sanitize = default_sanitize(&dir);
         OutputLock {
             lock: Arc::new(RwLock::new(dir)),
-            color: color
+            color: color,
+            sanitize: sanitize,
+            errors: ErrorCounter::new(),
+        }
+    }
+
+    #[inline]
+    pub fn with_sanitize(mut self, sanitize: SanitizeMode) -> Self {
+        self.sanitize = sanitize;
+        self
+    }
+
+    //按ColorMode决定是否着色, 在创建时判断一次;
+    //终端上保留消息中的换行, 只去掉ANSI序列和其它控制字符
+    #[inline]
+    pub fn with_color_mode(dir: T, mode: ColorMode) -> Self
+        where T: AsRawFd
+    {
+        let fd = dir.as_raw_fd();
+        let color = mode.enabled(fd);
+        let terminal = unsafe { libc::isatty(fd) == 1 };
+        let output = OutputLock::new(dir, color);
+        if terminal {
+            output.with_sanitize(SanitizeMode::Ansi)
+        } else {
+            output
         }
     }
 }
@@ -45,13 +229,100 @@ impl<T> ReadLock<T> for OutputLock<T>
 impl<T> Output for OutputLock<T>
     where T: Write + Send + Sync + 'static
 {
+    //整条记录连同换行在一次write里写出, O_APPEND打开的文件上不会和其它进程的记录交错
     #[inline]
-    fn push(&self, string: &str)
+    fn push(&self, string: &str) -> io::Result<()>
     {
         let mut output = self.lock.write().unwrap();
-        let _ = writeln!(&mut output, "{}", string);
+        output.write_all(string.as_bytes())
     }
     fn has_color(&self) -> bool {
         self.color
     }
-}
\ No newline at end of file
+
+    fn errors(&self) -> &ErrorCounter {
+        &self.errors
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.lock.write().unwrap().flush()
+    }
+
+    fn batch_end(&self) -> io::Result<()> {
+        Output::flush(self)
+    }
+
+    fn sanitize(&self) -> SanitizeMode {
+        self.sanitize
+    }
+}
+
+#[test]
+fn color_mode_auto() {
+    use std::fs;
+    use std::process;
+
+    let dir = env::temp_dir().join(format!("intro-color-mode-{}", process::id()));
+    let file = file::File::new(dir.join("out.log")).expect("can't open file");
+    let fd = file.as_raw_fd();
+    let vars = |force: Option<&str>, no_color: Option<&str>| {
+        let (force, no_color) = (force.map(OsString::from), no_color.map(OsString::from));
+        move |name: &str| match name {
+            "CLICOLOR_FORCE" => force.clone(),
+            "NO_COLOR" => no_color.clone(),
+            _ => None,
+        }
+    };
+
+    assert!(ColorMode::Always.enabled(fd));
+    assert!(!ColorMode::Never.enabled(fd));
+    assert!(!ColorMode::Auto.enabled_with(fd, vars(None, None)));
+    assert!(ColorMode::Auto.enabled_with(fd, vars(Some("1"), Some("1"))));
+    assert!(!ColorMode::Auto.enabled_with(fd, vars(Some("0"), Some("1"))));
+    assert!(!ColorMode::Auto.enabled_with(fd, vars(Some(""), None)));
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn output_lock_sanitize_defaults() {
+    let terminal = unsafe { libc::isatty(libc::STDOUT_FILENO) == 1 };
+    let stdout = if terminal { SanitizeMode::Ansi } else { SanitizeMode::Full };
+    assert_eq!(OutputLock::new(io::stdout(), false).sanitize(), stdout);
+    assert_eq!(OutputLock::new(Vec::new(), false).sanitize(), SanitizeMode::Full);
+    assert_eq!(OutputLock::new(Vec::new(), false).with_sanitize(SanitizeMode::Off).sanitize(), SanitizeMode::Off);
+}
+
+#[test]
+fn report_write_errors() {
+    use std::io::{Error, ErrorKind};
+
+    struct Full;
+    impl Write for Full {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(Error::new(ErrorKind::Other, "report_write_errors: device full"))
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let seen = Arc::new(AtomicUsize::new(0));
+    let counter = seen.clone();
+    set_error_handler(Arc::new(move |err: &io::Error, count: usize| {
+        if err.to_string() == "report_write_errors: device full" {
+            counter.store(count, Ordering::SeqCst);
+        }
+    }));
+
+    let output = OutputLock::new(Full, false);
+    for _ in 0..3 {
+        let result = output.push("lost\n");
+        assert!(result.is_err());
+        report(&output, result);
+    }
+    report(&output, output.flush());
+    assert_eq!(output.errors().count(), 3);
+    clear_error_handler();
+    assert_eq!(seen.load(Ordering::SeqCst), 3);
+}
@@ -0,0 +1,675 @@
+use std::cmp;
+use std::fs;
+use std::fmt::Write as FmtWrite;
+use std::io::{Error, ErrorKind, Result};
+use std::io::Write;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use chrono::format::{Item, Numeric, Fixed, StrftimeItems};
+use regex::{self, Regex};
+use time::{self, Timespec};
+use super::file::{File, FileBuilder, Durability};
+use super::{Output, ErrorCounter};
+use super::compress::{self, Compressor};
+use format::{self, Zone};
+use LogEntry;
+
+//按大小滚动的文件, 超过max_size后把当前文件改名为`path.1`, 已有的备份依次后移,
+//最多保留backups个备份, 更旧的删除
+pub struct SizeRollingFile {
+    path: PathBuf,
+    //滚动时用同样的权限新建文件
+    builder: FileBuilder,
+    file: File,
+    size: u64,
+    max_size: u64,
+    backups: usize,
+    compressor: Option<Compressor>,
+    durability: Durability,
+    //滚动失败的错误, 由下一次flush返回
+    rotate_error: Option<Error>,
+}
+
+impl SizeRollingFile {
+    pub fn new<P: AsRef<Path>>(path: P, max_size: u64, backups: usize) -> Result<SizeRollingFile> {
+        SizeRollingFile::from_builder(FileBuilder::new(path), max_size, backups)
+    }
+
+    //按builder的路径和权限打开, 例如`FileBuilder::new("app.log").mode(0o640)`
+    pub fn from_builder(builder: FileBuilder, max_size: u64, backups: usize) -> Result<SizeRollingFile> {
+        let file = builder.build()?;
+        let size = file.metadata()?.len();
+
+        Ok(SizeRollingFile {
+            path: builder.path().to_path_buf(),
+            builder: builder,
+            file: file,
+            size: size,
+            max_size: max_size,
+            backups: backups,
+            compressor: None,
+            durability: Durability::None,
+            rotate_error: None,
+        })
+    }
+
+    #[inline]
+    pub fn with_durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self.file = self.file.with_durability(durability);
+        self
+    }
+
+    //备份交给compressor在后台压缩, 得到`path.1.gz`等
+    #[inline]
+    pub fn with_compressor(mut self, compressor: Compressor) -> Self {
+        self.compressor = Some(compressor);
+        self
+    }
+
+    pub fn backup_path(&self, index: usize) -> PathBuf {
+        let mut name = self.path.as_os_str().to_os_string();
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        //失败时也重新计数, 再写max_size字节后才重试
+        self.size = 0;
+
+        //先把当前文件移开并打开新文件, 都成功之后才删除和移动备份, 失败时备份保持不变
+        let rotating = compress::append_extension(&self.path, "rotating");
+        fs::rename(&self.path, &rotating)?;
+        let file = match self.builder.build() {
+            Ok(file) => file.with_durability(self.durability),
+            Err(e) => {
+                //继续写旧文件, 并把它改回原来的名字
+                let _ = fs::rename(&rotating, &self.path);
+                return Err(e);
+            }
+        };
+
+        if self.backups == 0 {
+            self.file = file;
+            return fs::remove_file(&rotating);
+        }
+
+        //备份不存在时改名失败, 忽略即可
+        let _ = fs::remove_file(self.backup_path(self.backups));
+        for i in (1..self.backups).rev() {
+            let _ = fs::rename(self.backup_path(i), self.backup_path(i + 1));
+        }
+        if let Some(ref compressor) = self.compressor {
+            let _ = fs::remove_file(compressor.compressed_path(&self.backup_path(self.backups)));
+            for i in (1..self.backups).rev() {
+                let _ = fs::rename(compressor.compressed_path(&self.backup_path(i)),
+                                   compressor.compressed_path(&self.backup_path(i + 1)));
+            }
+        }
+        if let Err(e) = fs::rename(&rotating, self.backup_path(1)) {
+            //新文件还没有写过, 删掉它, 把旧文件改回原来的名字继续写
+            drop(file);
+            let _ = fs::remove_file(&self.path);
+            let _ = fs::rename(&rotating, &self.path);
+            return Err(e);
+        }
+        self.file = file;
+
+        if let Some(ref compressor) = self.compressor {
+            compressor.submit(self.backup_path(1));
+        }
+        Ok(())
+    }
+}
+
+impl Write for SizeRollingFile {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let n = self.file.write(buf)?;
+        self.size += n as u64;
+
+        //writeln!会分多次写入, 只在一行写完之后切换;
+        //备份还在压缩时不能改名, 先继续写当前文件, 压缩完之后的下一行再切换;
+        //内容已经写入, 切换失败也返回成功, 以免调用方重写造成重复, 错误留给flush返回
+        let idle = self.compressor.as_ref().map_or(true, |compressor| compressor.is_idle());
+        if self.size >= self.max_size && n == buf.len() && buf.ends_with(b"\n") && idle {
+            if let Err(e) = self.rotate() {
+                self.rotate_error = Some(e);
+            }
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if let Some(e) = self.rotate_error.take() {
+            return Err(e);
+        }
+        self.file.flush()
+    }
+}
+
+//按记录时间滚动的文件, 路径是strftime模板, 例如`logs/app-%Y-%m-%d.log`
+//用LogEntry的时间计算路径, 迟到的记录仍然写入它所属的文件
+pub struct TimeRollingFile {
+    pattern: String,
+    //模板中最小的时间单位(秒), 为0时每条记录都重新计算路径
+    unit: i64,
+    zone: Zone,
+    mode: Option<u32>,
+    dir_mode: Option<u32>,
+    max_age: Option<Duration>,
+    compressor: Option<Compressor>,
+    durability: Durability,
+    periods: Mutex<Periods>,
+    errors: ErrorCounter,
+}
+
+//一个时间段的文件, lo和hi是已经写进这个文件的最早和最晚的记录时间(秒)
+struct Period {
+    path: String,
+    file: File,
+    lo: i64,
+    hi: i64,
+}
+
+//保持最新和上一个时间段的文件打开, 跨时间段交替的记录不用重新打开文件
+struct Periods {
+    newest: Option<Period>,
+    previous: Option<Period>,
+    //计算路径时重用
+    scratch: String,
+    //文件正在压缩的时间段的迟到记录, 压缩完之后写回各自的文件
+    held: Vec<(String, Vec<u8>)>,
+}
+
+impl Periods {
+    //时间段是连续的, 相差不到一个单位的两个记录写进了同一个文件, 它们之间的记录也属于这个文件
+    fn find(&mut self, sec: i64, unit: i64) -> Option<&mut Period> {
+        if unit == 0 {
+            return None;
+        }
+        let contains = |period: &Period| period.lo <= sec && sec <= period.hi;
+        match (self.newest.as_ref().map_or(false, &contains), self.previous.as_ref().map_or(false, &contains)) {
+            (true, _) => self.newest.as_mut(),
+            (_, true) => self.previous.as_mut(),
+            _ => None,
+        }
+    }
+
+    //按scratch中的路径查找已经打开的文件
+    fn matching(&mut self, sec: i64, unit: i64) -> Option<&mut Period> {
+        let scratch = &self.scratch;
+        let period = match (self.newest.as_mut(), self.previous.as_mut()) {
+            (Some(newest), _) if newest.path == *scratch => newest,
+            (_, Some(previous)) if previous.path == *scratch => previous,
+            _ => return None,
+        };
+        let (lo, hi) = (cmp::min(period.lo, sec), cmp::max(period.hi, sec));
+        if hi - lo < unit {
+            period.lo = lo;
+            period.hi = hi;
+        }
+        Some(period)
+    }
+}
+
+//模板中最小的时间单位, 含有秒以下的字段时为0
+fn pattern_unit(pattern: &str) -> i64 {
+    StrftimeItems::new(pattern).map(|item| match item {
+        Item::Numeric(Numeric::Second, _) | Item::Numeric(Numeric::Timestamp, _) => 1,
+        Item::Numeric(Numeric::Minute, _) => 60,
+        Item::Numeric(Numeric::Hour, _) | Item::Numeric(Numeric::Hour12, _) => 3600,
+        Item::Fixed(Fixed::LowerAmPm) | Item::Fixed(Fixed::UpperAmPm) => 3600,
+        Item::Numeric(Numeric::Nanosecond, _) | Item::Fixed(Fixed::Nanosecond) |
+        Item::Fixed(Fixed::Nanosecond3) | Item::Fixed(Fixed::Nanosecond6) |
+        Item::Fixed(Fixed::Nanosecond9) | Item::Fixed(Fixed::RFC2822) | Item::Fixed(Fixed::RFC3339) => 0,
+        _ => 86400,
+    }).min().unwrap_or(86400)
+}
+
+impl TimeRollingFile {
+    pub fn new(pattern: &str) -> Result<TimeRollingFile> {
+        if StrftimeItems::new(pattern).any(|item| item == Item::Error) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("invalid date pattern: {}", pattern)));
+        }
+
+        Ok(TimeRollingFile {
+            pattern: pattern.to_string(),
+            unit: pattern_unit(pattern),
+            zone: Zone::Local,
+            mode: None,
+            dir_mode: None,
+            max_age: None,
+            compressor: None,
+            durability: Durability::None,
+            periods: Mutex::new(Periods {
+                newest: None,
+                previous: None,
+                scratch: String::new(),
+                held: Vec::new(),
+            }),
+            errors: ErrorCounter::new(),
+        })
+    }
+
+    #[inline]
+    pub fn with_zone(mut self, zone: Zone) -> Self {
+        self.zone = zone;
+        self
+    }
+
+    //每个时间段的文件和创建的目录的权限, 同FileBuilder::mode和FileBuilder::dir_mode
+    #[inline]
+    pub fn with_mode(mut self, mode: u32) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    #[inline]
+    pub fn with_dir_mode(mut self, mode: u32) -> Self {
+        self.dir_mode = Some(mode);
+        self
+    }
+
+    //切换到新的时间段时删除修改时间早于max_age的旧文件, 只检查当前文件所在的目录
+    #[inline]
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    //不再保持打开的旧文件交给compressor压缩
+    #[inline]
+    pub fn with_compressor(mut self, compressor: Compressor) -> Self {
+        self.compressor = Some(compressor);
+        self
+    }
+
+    #[inline]
+    pub fn with_durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    pub fn path_at(&self, time: Timespec) -> PathBuf {
+        let date_time = format::get_record_date_time(time, self.zone);
+        PathBuf::from(date_time.format(&self.pattern).to_string())
+    }
+
+    fn write(&self, time: Timespec, string: &str) -> Result<()> {
+        let sec = time.sec;
+        let mut periods = self.periods.lock().unwrap();
+        let periods = &mut *periods;
+
+        //先写回之前留下的迟到记录, 保持同一个文件中的顺序
+        if !periods.held.is_empty() {
+            if let Err(ref err) = self.release_held(periods) {
+                self.errors.record(err);
+            }
+        }
+
+        if let Some(period) = periods.find(sec, self.unit) {
+            return period.file.write_all(string.as_bytes());
+        }
+
+        periods.scratch.clear();
+        let date_time = format::get_record_date_time(time, self.zone);
+        let _ = write!(periods.scratch, "{}", date_time.format(&self.pattern));
+
+        if let Some(period) = periods.matching(sec, self.unit) {
+            return period.file.write_all(string.as_bytes());
+        }
+
+        let newer = periods.newest.as_ref().map_or(true, |newest| sec > newest.hi);
+        if !newer {
+            //比打开的两个时间段更早的记录, 单独打开它的文件写入, 写完就交给compressor;
+            //它的文件正在压缩时不能重新打开, 先留在内存里, 压缩完之后再写回
+            let compressing = self.compressor.as_ref().map_or(false, |compressor| compressor.is_pending(Path::new(&periods.scratch)));
+            if !compressing {
+                return self.write_late(&periods.scratch, string.as_bytes());
+            }
+            let scratch = &periods.scratch;
+            match periods.held.iter_mut().find(|&&mut (ref path, _)| path == scratch) {
+                Some(&mut (_, ref mut lines)) => lines.extend_from_slice(string.as_bytes()),
+                None => periods.held.push((scratch.clone(), string.as_bytes().to_vec())),
+            }
+            return Ok(());
+        }
+
+        let file = match self.open(&periods.scratch) {
+            Ok(file) => file,
+            //打开失败时继续写原来的文件, 再返回打开的错误
+            Err(e) => return match periods.newest {
+                Some(ref mut newest) => newest.file.write_all(string.as_bytes()).and(Err(e)),
+                None => Err(e),
+            },
+        };
+        let period = Period {
+            path: periods.scratch.clone(),
+            file: file,
+            lo: sec,
+            hi: sec,
+        };
+        let newest = periods.newest.take();
+        if let Some(evicted) = mem::replace(&mut periods.previous, newest) {
+            let Period { path, file, .. } = evicted;
+            drop(file);
+            if let Some(ref compressor) = self.compressor {
+                compressor.submit(PathBuf::from(path));
+            }
+        }
+        periods.newest = Some(period);
+
+        if let Some(max_age) = self.max_age {
+            if let Err(ref err) = self.cleanup(periods, max_age) {
+                self.errors.record(err);
+            }
+        }
+        periods.newest.as_mut().unwrap().file.write_all(string.as_bytes())
+    }
+
+    fn open(&self, path: &str) -> Result<File> {
+        let mut builder = FileBuilder::new(path);
+        if let Some(mode) = self.mode {
+            builder.mode(mode);
+        }
+        if let Some(dir_mode) = self.dir_mode {
+            builder.dir_mode(dir_mode);
+        }
+        builder.build().map(|file| file.with_durability(self.durability))
+    }
+
+    fn write_late(&self, path: &str, lines: &[u8]) -> Result<()> {
+        let mut file = self.open(path)?;
+        file.write_all(lines)?;
+        file.flush()?;
+        drop(file);
+        if let Some(ref compressor) = self.compressor {
+            compressor.submit(PathBuf::from(path));
+        }
+        Ok(())
+    }
+
+    //写回已经压缩完的时间段的迟到记录, 写失败的留到下一次再试
+    fn release_held(&self, periods: &mut Periods) -> Result<()> {
+        let mut result = Ok(());
+        let mut i = 0;
+        while i < periods.held.len() {
+            let done = {
+                let (ref path, ref lines) = periods.held[i];
+                let compressing = self.compressor.as_ref().map_or(false, |compressor| compressor.is_pending(Path::new(path)));
+                !compressing && match self.write_late(path, lines) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        result = Err(e);
+                        false
+                    }
+                }
+            };
+            if done {
+                periods.held.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+        result
+    }
+
+    //跳过仍然打开的文件
+    fn cleanup(&self, periods: &Periods, max_age: Duration) -> Result<()> {
+        let name = match Path::new(&self.pattern).file_name().and_then(|name| name.to_str()) {
+            Some(name) => name,
+            None => return Ok(()),
+        };
+        let matcher = pattern_regex(name);
+        let current = Path::new(&periods.newest.as_ref().unwrap().path);
+        let previous = periods.previous.as_ref().map(|previous| Path::new(&previous.path));
+        let dir = match current.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let now = SystemTime::now();
+
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if path.file_name() == current.file_name() || previous.map_or(false, |p| path.file_name() == p.file_name()) {
+                continue;
+            }
+            if !entry.file_name().to_str().map_or(false, |name| matcher.is_match(name)) {
+                continue;
+            }
+            let expired = entry.metadata()?.modified()
+                .map(|modified| now.duration_since(modified).map_or(false, |age| age >= max_age))
+                .unwrap_or(false);
+            if expired {
+                let _ = fs::remove_file(&path);
+            }
+        }
+        Ok(())
+    }
+}
+
+//把文件名模板转成匹配已有文件的正则, 日期部分匹配任意字符
+fn pattern_regex(pattern: &str) -> Regex {
+    let mut expr = String::from("^");
+    for item in StrftimeItems::new(pattern) {
+        match item {
+            Item::Literal(s) | Item::Space(s) => expr.push_str(&regex::escape(s)),
+            _ => expr.push_str(".+?"),
+        }
+    }
+    //包括压缩后的文件
+    expr.push_str(r"(?:\.gz|\.zst)?$");
+    Regex::new(&expr).unwrap()
+}
+
+impl Output for TimeRollingFile {
+    fn push(&self, string: &str) -> Result<()> {
+        self.write(time::get_time(), string)
+    }
+
+    fn push_record(&self, record: &LogEntry, string: &str) -> Result<()> {
+        self.write(record.time(), string)
+    }
+
+    fn has_color(&self) -> bool {
+        false
+    }
+
+    fn errors(&self) -> &ErrorCounter {
+        &self.errors
+    }
+
+    fn flush(&self) -> Result<()> {
+        let mut periods = self.periods.lock().unwrap();
+        let periods = &mut *periods;
+        let mut result = self.release_held(periods);
+        for period in periods.newest.iter_mut().chain(periods.previous.iter_mut()) {
+            result = result.and(period.file.flush());
+        }
+        result
+    }
+
+    fn batch_end(&self) -> Result<()> {
+        Output::flush(self)
+    }
+}
+
+//释放时等压缩结束, 把留在内存里的迟到记录写回
+impl Drop for TimeRollingFile {
+    fn drop(&mut self) {
+        let held = !self.periods.lock().unwrap().held.is_empty();
+        if let (true, Some(compressor)) = (held, self.compressor.as_ref()) {
+            compressor.wait();
+        }
+        let result = Output::flush(self);
+        super::report(self, result);
+    }
+}
+
+#[test]
+fn size_rolling_keeps_lines() {
+    use std::env;
+    use std::os::unix::fs::PermissionsExt;
+    use std::process;
+    use output::{Output, OutputLock};
+
+    let mode = |p: &Path| fs::metadata(p).unwrap().permissions().mode() & 0o7777;
+    let dir = env::temp_dir().join(format!("intro-size-rolling-{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let path = dir.join("app.log");
+
+    let rolling = SizeRollingFile::from_builder(FileBuilder::new(&path).mode(0o660).clone(), 20, 2).unwrap();
+    let backup1 = rolling.backup_path(1);
+    let backup2 = rolling.backup_path(2);
+    let output = OutputLock::new(rolling, false);
+
+    for i in 1..11 {
+        output.push(&format!("line {}\n", i)).unwrap();
+    }
+
+    let read = |p: &PathBuf| fs::read_to_string(p).unwrap();
+    assert_eq!(read(&backup2), "line 4\nline 5\nline 6\n");
+    assert_eq!(read(&backup1), "line 7\nline 8\nline 9\n");
+    assert_eq!(read(&path), "line 10\n");
+    assert!(!dir.join("app.log.3").exists());
+    //滚动时新建的文件同样按mode设置权限
+    assert_eq!(mode(&path), 0o660);
+    assert_eq!(mode(&backup1), 0o660);
+
+    //备份位置被目录占住时滚动失败, 继续写原文件并由flush报告
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("app.log.1").join("busy")).unwrap();
+    let output = OutputLock::new(SizeRollingFile::new(&path, 20, 1).unwrap(), false);
+    for i in 1..5 {
+        output.push(&format!("line {}\n", i)).unwrap();
+    }
+    assert!(output.flush().is_err());
+    assert!(output.flush().is_ok());
+    assert_eq!(read(&path), "line 1\nline 2\nline 3\nline 4\n");
+    assert!(dir.join("app.log.1").join("busy").exists());
+
+    //当前文件移不开时不动已有的备份
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("app.log.rotating").join("busy")).unwrap();
+    fs::write(dir.join("app.log.1"), "old 1\n").unwrap();
+    fs::write(dir.join("app.log.2"), "old 2\n").unwrap();
+    let output = OutputLock::new(SizeRollingFile::new(&path, 20, 2).unwrap(), false);
+    for i in 1..8 {
+        output.push(&format!("line {}\n", i)).unwrap();
+    }
+    assert!(output.flush().is_err());
+    assert_eq!(read(&path), "line 1\nline 2\nline 3\nline 4\nline 5\nline 6\nline 7\n");
+    assert_eq!(read(&dir.join("app.log.1")), "old 1\n");
+    assert_eq!(read(&dir.join("app.log.2")), "old 2\n");
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn time_rolling_by_record_time() {
+    use std::env;
+    use std::os::unix::fs::PermissionsExt;
+    use std::process;
+    use log::LogLevel;
+
+    let dir = env::temp_dir().join(format!("intro-time-rolling-{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let pattern = format!("{}/app-%Y%m%d%H.log", dir.display());
+
+    let output = TimeRollingFile::new(&pattern).unwrap().with_zone(Zone::Utc).with_mode(0o660);
+    //2017-01-01 10:59:59 UTC
+    let push = |sec: i64, msg: &str| {
+        let mut entry = format::test_entry(LogLevel::Info, "intro", "rolling.rs", 1, msg);
+        entry.time = Timespec::new(1483268399 + sec, 0);
+        output.push_record(&entry, &format!("{}\n", msg)).unwrap();
+    };
+    push(0, "first");
+    push(1, "second");
+    push(0, "late");
+    push(2, "third");
+    push(-3600, "earlier");
+    push(-1, "later");
+
+    let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+    assert_eq!(read("app-2017010109.log"), "earlier\n");
+    assert_eq!(read("app-2017010110.log"), "first\nlate\nlater\n");
+    assert_eq!(read("app-2017010111.log"), "second\nthird\n");
+    let mode = |name: &str| fs::metadata(dir.join(name)).unwrap().permissions().mode() & 0o7777;
+    assert_eq!(mode("app-2017010109.log"), 0o660);
+    assert_eq!(mode("app-2017010111.log"), 0o660);
+    assert_eq!(pattern_unit("app-%Y%m%d.log"), 86400);
+    assert_eq!(pattern_unit("app-%Y%m%d-%H%M.%f.log"), 0);
+
+    fs::write(dir.join("other.log"), "keep").unwrap();
+    let expiring = TimeRollingFile::new(&pattern).unwrap().with_zone(Zone::Utc).with_max_age(Duration::from_secs(0));
+    let mut entry = format::test_entry(LogLevel::Info, "intro", "rolling.rs", 1, "next");
+    entry.time = Timespec::new(1483268399 + 3601, 0);
+    expiring.push_record(&entry, "next\n").unwrap();
+    assert!(!dir.join("app-2017010110.log").exists());
+    assert!(!dir.join("app-2017010111.log").exists());
+    assert_eq!(read("app-2017010112.log"), "next\n");
+    assert_eq!(read("other.log"), "keep");
+
+    assert!(TimeRollingFile::new("app-%Q.log").is_err());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn time_rolling_late_record_while_compressing() {
+    use std::env;
+    use std::ffi::CString;
+    use std::fs::{File as FsFile, OpenOptions};
+    use std::io::Read;
+    use std::os::unix::ffi::OsStrExt;
+    use std::process;
+    use flate2::read::MultiGzDecoder;
+    use libc;
+    use log::LogLevel;
+    use output::compress::Compression;
+
+    let dir = env::temp_dir().join(format!("intro-time-compress-{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let pattern = format!("{}/app-%Y%m%d%H.log", dir.display());
+
+    //compressor先读一个FIFO, 在打开写端之前一直阻塞, 之后提交的文件都停在队列里
+    let fifo = dir.join("blocker");
+    let name = CString::new(fifo.as_os_str().as_bytes()).unwrap();
+    assert_eq!(unsafe { libc::mkfifo(name.as_ptr(), 0o600) }, 0);
+    let compressor = Compressor::new(Compression::Gzip);
+    compressor.submit(fifo.clone());
+
+    let output = TimeRollingFile::new(&pattern).unwrap().with_zone(Zone::Utc).with_compressor(compressor.clone());
+    //2017-01-01 10:00:00 UTC
+    let push = |sec: i64, msg: &str| {
+        let mut entry = format::test_entry(LogLevel::Info, "intro", "rolling.rs", 1, msg);
+        entry.time = Timespec::new(1483264800 + sec, 0);
+        output.push_record(&entry, &format!("{}\n", msg)).unwrap();
+    };
+    push(0, "first");
+    push(3600, "second");
+    push(7200, "third");
+    assert!(compressor.is_pending(&dir.join("app-2017010110.log")));
+    push(1, "late");
+
+    let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+    assert_eq!(read("app-2017010110.log"), "first\n");
+    assert_eq!(read("app-2017010111.log"), "second\n");
+    assert_eq!(read("app-2017010112.log"), "third\n");
+
+    drop(OpenOptions::new().write(true).open(&fifo).unwrap());
+    compressor.wait();
+    output.flush().unwrap();
+    compressor.wait();
+
+    let mut text = String::new();
+    MultiGzDecoder::new(FsFile::open(dir.join("app-2017010110.log.gz")).unwrap()).unwrap().read_to_string(&mut text).unwrap();
+    assert_eq!(text, "first\nlate\n");
+    assert!(!dir.join("app-2017010110.log").exists());
+
+    drop(output);
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,136 @@
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum State {
+    Normal,
+    //ESC之后
+    Escape,
+    //CSI序列, 以0x40-0x7e结束
+    Csi,
+    //OSC/DCS等字符串序列, 以BEL或ESC \结束
+    Str,
+    StrEscape,
+}
+
+//输出端对消息等用户字段的处理方式, 由Output::sanitize提供
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SanitizeMode {
+    //原样写出
+    Off,
+    //去掉ANSI序列并转义控制字符, 但保留换行, 用于终端
+    Ansi,
+    //去掉ANSI序列并转义包括换行在内的控制字符, 用于文件
+    Full,
+}
+
+//转义控制字符并去掉其中的ANSI转义序列, 防止用户输入伪造日志行或者改变终端颜色
+//`\n`和`\r`写成`\\n`和`\\r`, 其余控制字符写成`\\x1f`或`\\u{9f}`, 制表符保留
+pub struct Sanitizer<'a> {
+    inner: &'a mut fmt::Write,
+    state: State,
+    keep_newlines: bool,
+}
+
+impl<'a> Sanitizer<'a> {
+    pub fn new(inner: &'a mut fmt::Write) -> Sanitizer<'a> {
+        Sanitizer {
+            inner: inner,
+            state: State::Normal,
+            keep_newlines: false,
+        }
+    }
+
+    //`\n`原样写出, 其余和new相同
+    pub fn keep_newlines(inner: &'a mut fmt::Write) -> Sanitizer<'a> {
+        Sanitizer {
+            inner: inner,
+            state: State::Normal,
+            keep_newlines: true,
+        }
+    }
+
+    #[inline]
+    fn push(&mut self, c: char) -> fmt::Result {
+        self.state = match (self.state, c) {
+            (State::Normal, '\u{1b}') => State::Escape,
+            (State::Normal, '\u{9b}') => State::Csi,
+            (State::Normal, '\n') if self.keep_newlines => {
+                self.inner.write_char(c)?;
+                State::Normal
+            }
+            (State::Normal, '\t') => {
+                self.inner.write_char(c)?;
+                State::Normal
+            }
+            (State::Normal, '\n') => {
+                self.inner.write_str("\\n")?;
+                State::Normal
+            }
+            (State::Normal, '\r') => {
+                self.inner.write_str("\\r")?;
+                State::Normal
+            }
+            (State::Normal, c) if (c as u32) < 0x20 || c == '\u{7f}' => {
+                write!(self.inner, "\\x{:02x}", c as u32)?;
+                State::Normal
+            }
+            (State::Normal, c) if c.is_control() => {
+                write!(self.inner, "\\u{{{:x}}}", c as u32)?;
+                State::Normal
+            }
+            (State::Normal, c) => {
+                self.inner.write_char(c)?;
+                State::Normal
+            }
+            (State::Escape, '[') => State::Csi,
+            (State::Escape, ']') | (State::Escape, 'P') | (State::Escape, 'X') |
+            (State::Escape, '^') | (State::Escape, '_') => State::Str,
+            //其它两个字符的序列
+            (State::Escape, _) => State::Normal,
+            (State::Csi, c) if c >= '\u{40}' && c <= '\u{7e}' => State::Normal,
+            (State::Csi, _) => State::Csi,
+            (State::Str, '\u{7}') | (State::Str, '\u{9c}') => State::Normal,
+            (State::Str, '\u{1b}') => State::StrEscape,
+            (State::Str, _) => State::Str,
+            (State::StrEscape, '\\') => State::Normal,
+            (State::StrEscape, _) => State::Str,
+        };
+        Ok(())
+    }
+}
+
+impl<'a> fmt::Write for Sanitizer<'a> {
+    #[inline]
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.state == State::Normal && !s.chars().any(|c| c != '\t' && c.is_control()) {
+            return self.inner.write_str(s);
+        }
+        for c in s.chars() {
+            self.push(c)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn sanitize_control_characters() {
+    use std::fmt::Write;
+
+    let cases = [
+        ("plain\ttext", "plain\ttext"),
+        ("user=bob\n2017-01-01 ERROR forged", "user=bob\\n2017-01-01 ERROR forged"),
+        ("a\r\nb\u{0}c\u{7f}", "a\\r\\nb\\x00c\\x7f"),
+        ("\u{1b}[31mred\u{1b}[0m \u{9b}1;2Hmoved", "red moved"),
+        ("\u{1b}]0;title\u{7}ok \u{1b}]8;;http://x\u{1b}\\link", "ok link"),
+        ("\u{1b}creset\u{85}", "reset\\u{85}"),
+    ];
+    for &(input, expected) in cases.iter() {
+        let mut out = String::new();
+        Sanitizer::new(&mut out).write_str(input).unwrap();
+        assert_eq!(out, expected);
+    }
+
+    let mut out = String::new();
+    Sanitizer::keep_newlines(&mut out).write_str("line 1\n\u{1b}[2Jline\r2").unwrap();
+    assert_eq!(out, "line 1\nline\\r2");
+}